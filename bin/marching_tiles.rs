@@ -4,6 +4,7 @@ mod common;
 use bevy::prelude::*;
 use prototype_slenderish::world::{
     chunks::MarchingTileBundle,
+    seed::{SeedStream, WorldSeed},
     wfc::{
         grid::{TileCell, WaveGrid},
         tile::TileID,
@@ -28,7 +29,9 @@ fn test_grid(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    let mut grid = WaveGrid::new(9);
+    let seed = WorldSeed::from_args_or_random(std::env::args());
+    info!("world seed: {}", seed.0);
+    let mut grid = WaveGrid::new(9, seed.derive(SeedStream::WaveCollapse));
     let origin = Transform::IDENTITY;
     let all_cells = grid.collapse_all_into_vec();
     for (_i, cell) in all_cells.into_iter().enumerate() {
@@ -0,0 +1,37 @@
+#[path = "../bin/common/lib.rs"]
+mod common;
+
+use bevy::prelude::*;
+use noise::{Fbm, MultiFractal, Perlin};
+use prototype_slenderish::world::{
+    planet::{build_planet, planet_collider},
+    seed::{SeedStream, WorldSeed},
+    terrain::TerrainBundle,
+};
+
+pub fn main() {
+    let mut app = common::test_app(false);
+    app.add_systems(Startup, setup_planet).run();
+}
+
+fn setup_planet(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let seed = WorldSeed::from_args_or_random(std::env::args());
+    info!("world seed: {}", seed.0);
+    let noise = Fbm::<Perlin>::new(seed.derive_u32(SeedStream::TerrainNoise))
+        .set_frequency(0.15)
+        .set_octaves(4);
+
+    let radius = 12.;
+    let planet = build_planet(&noise, radius, 2., 5);
+    let collider = planet_collider(&planet);
+    let mesh = planet.into_mesh(false, radius * 2.);
+
+    let mut bundle = TerrainBundle::with_collider(mesh, collider, &mut meshes, &mut materials);
+    bundle.name = Name::new("Planet");
+    bundle.transform = Transform::IDENTITY.into();
+    commands.spawn(bundle);
+}
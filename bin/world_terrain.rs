@@ -3,14 +3,24 @@ mod common;
 
 use bevy::prelude::*;
 use bevy_inspector_egui::prelude::*;
-use noise::{Fbm, Perlin};
 use prototype_slenderish::world::{
-    noise::NoiseSampler, rtin::build_terrain_from_sampler, terrain::TerrainBundle,
+    config::{TerrainConfig, TerrainConfigHandle, TerrainConfigPlugin},
+    jobs::{poll_terrain_builds, TerrainBuildFinished, TerrainBuildTask, TerrainJobsPlugin},
+    validation::{draw_mesh_issues, MeshReport},
 };
 
 pub fn main() {
     let mut app = common::test_app(false);
-    app.add_systems(Startup, setup_nosie)
+    app.add_plugins((TerrainConfigPlugin, TerrainJobsPlugin))
+        .add_systems(
+            Update,
+            (
+                rebuild_terrain,
+                // the mesh is inserted with commands, it exists after the sync point
+                validate_terrain.after(poll_terrain_builds),
+                draw_mesh_issues,
+            ),
+        )
         // .add_systems(Update, NoiseListener::update)
         .run();
 }
@@ -18,28 +28,30 @@ pub fn main() {
 #[reflect(Resource, InspectorOptions)]
 pub struct NoiseListener;
 
-fn setup_nosie(
+/// Builds the terrain once the config loads and again every time the file is saved
+fn rebuild_terrain(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    handle: Res<TerrainConfigHandle>,
+    configs: Res<Assets<TerrainConfig>>,
+    mut events: EventReader<AssetEvent<TerrainConfig>>,
+    mut terrain: Local<Option<Entity>>,
 ) {
-    let mut noise_func = Fbm::<Perlin>::new(5);
-    noise_func.lacunarity = 0.2;
-    noise_func.frequency = 0.1;
-    noise_func.octaves = 2;
-    noise_func.persistence = 0.2;
-    let sampler = NoiseSampler::single_layer(noise_func);
-    let size = 128.;
-    let height = 20.0;
+    if !events.read().any(|event| handle.changed(event)) {
+        return;
+    }
+    let Some(config) = configs.get(&handle.0) else {
+        return;
+    };
 
-    let terrain = build_terrain_from_sampler(&sampler, height, size, 0.01);
-    let mesh = terrain.into_mesh(false, size);
-    commands.insert_resource(sampler);
-
-    let mut bundle = TerrainBundle::new(mesh, &mut meshes, &mut materials);
-    let transform = Transform::from_xyz(-50., 0., -50.);
-    bundle.transform = transform.into();
-    commands.spawn(bundle);
+    let mut request = config.terrain_request();
+    request.transform = Transform::from_xyz(-50., 0., -50.);
+    let task = TerrainBuildTask::spawn(request);
+    match *terrain {
+        Some(entity) => {
+            commands.entity(entity).insert(task);
+        }
+        None => *terrain = Some(commands.spawn(task).id()),
+    }
 }
 //
 //
@@ -88,3 +100,26 @@ fn setup_nosie(
 //         }
 //     }
 // }
+
+/// Checks every finished build, issues are outlined on the terrain by `draw_mesh_issues`
+fn validate_terrain(
+    mut commands: Commands,
+    meshes: Res<Assets<Mesh>>,
+    mut finished: EventReader<TerrainBuildFinished>,
+    mesh_q: Query<&Handle<Mesh>>,
+) {
+    for event in finished.read() {
+        let Some(report) = mesh_q
+            .get(event.entity)
+            .ok()
+            .and_then(|handle| meshes.get(handle))
+            .and_then(MeshReport::from_mesh)
+        else {
+            continue;
+        };
+        for issue in report.errors() {
+            warn!("terrain mesh: {issue:?}");
+        }
+        commands.entity(event.entity).insert(report);
+    }
+}
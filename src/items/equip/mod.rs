@@ -1,7 +1,13 @@
 use std::{fmt::Debug, sync::LazyLock};
 mod events;
 
-use crate::player::PlayerViewModelExtension;
+use crate::{
+    player::PlayerViewModelExtension,
+    world::{
+        query::SnapToTerrain,
+        seed::{SeedStream, WorldSeed},
+    },
+};
 use bevy::{
     pbr::{ExtendedMaterial, StandardMaterial},
     prelude::*,
@@ -9,6 +15,7 @@ use bevy::{
 use bevy_rapier3d::prelude::{CollisionGroups, Group};
 use inventory::{player_raycast, update_player_equipment, Inventory, WorldEquipHandle};
 use player::PlayerEquipItem;
+use rand::Rng;
 use world::{
     cube::{systems::update_cubes, WorldCubeBundle},
     sphere::{
@@ -85,20 +92,29 @@ fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<EquipItemMaterial>>,
+    seed: Res<WorldSeed>,
     // mut ev_equip_item: EventWriter<EquipItemEvent>,
 ) {
-    let t = Transform::from_xyz(8.0, 8.0, 8.0);
+    // items wait at these heights until the terrain under them loads, then drop onto it
+    let drop = SnapToTerrain { offset: 1. };
+    // every item lands somewhere around its spot, the same place for the same seed
+    let mut rng = seed.rng(SeedStream::Items);
+    let mut scatter = |x: f32, y: f32, z: f32| {
+        Transform::from_xyz(x + rng.gen_range(-4. ..4.), y, z + rng.gen_range(-4. ..4.))
+    };
+
+    let t = scatter(8.0, 8.0, 8.0);
     let ball_world = WorldSphereBundle::bundle(t, &mut meshes, &mut materials);
 
-    commands.spawn(ball_world);
+    commands.spawn((ball_world, drop));
 
-    let t = Transform::from_xyz(18.0, 18.0, 18.0);
+    let t = scatter(18.0, 18.0, 18.0);
     let cube_world = WorldCubeBundle::bundle(t, &mut meshes, &mut materials);
-    commands.spawn(cube_world);
-    let t = Transform::from_xyz(28.0, 18.0, 18.0);
+    commands.spawn((cube_world, drop));
+    let t = scatter(28.0, 18.0, 18.0);
     let cube_world = WorldCubeBundle::bundle(t, &mut meshes, &mut materials);
 
-    commands.spawn(cube_world);
+    commands.spawn((cube_world, drop));
 
     commands.spawn(Inventory::default());
 }
@@ -1,8 +1,11 @@
 use super::{
     EffectTimer, PlayerEquipSphere, WorldEquipSphere, WorldSphereBundle, MS_TO_CLEAR_EXPLOSION,
 };
-use crate::items::equip::inventory::Inventory;
-use bevy::{color::palettes::css::RED, prelude::*};
+use crate::{
+    items::equip::inventory::Inventory,
+    world::deformation::{DeformTerrain, TerrainBrush},
+};
+use bevy::{color::palettes::css::RED, math::Vec3Swizzles, prelude::*};
 use bevy_rapier3d::{
     na::{distance_squared, ComplexField, Point3},
     prelude::{ExternalImpulse, RigidBody},
@@ -11,6 +14,7 @@ use std::time::Duration;
 
 // ### SYSTEMS
 
+#[allow(clippy::too_many_arguments)]
 pub fn sphere_dropped(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
@@ -19,6 +23,7 @@ pub fn sphere_dropped(
     time: Res<Time>,
     rigid_bodies: Query<(Entity, &GlobalTransform), With<RigidBody>>,
     mut q: Query<(Entity, &mut WorldEquipSphere, &GlobalTransform), With<WorldEquipSphere>>,
+    mut deform_terrain: EventWriter<DeformTerrain>,
 ) {
     let mut inventory = inventory_q.single_mut();
     let mut explosion_bundle = Option::<PbrBundle>::None;
@@ -81,6 +86,12 @@ pub fn sphere_dropped(
                     }
                 }
 
+                deform_terrain.send(DeformTerrain {
+                    brush: TerrainBrush::Crater { depth: 2. },
+                    center: explosion_transform.translation.xz(),
+                    radius: 4.,
+                });
+
                 if let Some(bundle) = explosion_bundle.take() {
                     commands.spawn((
                         bundle,
@@ -2,12 +2,25 @@ use bevy::prelude::*;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_rapier3d::prelude::*;
 use prototype_slenderish::{
-    items::ItemsPlugin, npc::NpcPlugin, player::PlayerPlugin, world::WorldPlugin,
+    items::ItemsPlugin,
+    npc::NpcPlugin,
+    player::PlayerPlugin,
+    world::{seed::WorldSeed, WorldPlugin},
 };
 
 fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins)
+    let mut app = App::new();
+    match WorldSeed::from_args(std::env::args()) {
+        Ok(Some(seed)) => {
+            app.insert_resource(seed);
+        }
+        Ok(None) => {}
+        Err(err) => {
+            eprintln!("--seed expects a number: {err}");
+            std::process::exit(2);
+        }
+    }
+    app.add_plugins(DefaultPlugins)
         .add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
         .add_plugins(RapierDebugRenderPlugin::default())
         // .add_plugins(DefaultPickingPlugins)
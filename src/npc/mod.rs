@@ -1,7 +1,12 @@
 use bevy::{color::palettes::css::WHITE, prelude::*};
 use bevy_rapier3d::prelude::*;
 
-use crate::world::GROUND_Y;
+use crate::world::{
+    query::SnapToTerrain,
+    seed::{SeedStream, WorldSeed},
+    GROUND_Y,
+};
+use rand::Rng;
 
 pub struct NpcPlugin;
 
@@ -26,6 +31,7 @@ struct NpcBundle {
 
 impl NpcBundle {
     pub fn new(
+        position: Vec2,
         meshes: &mut ResMut<Assets<Mesh>>,
         materials: &mut ResMut<Assets<StandardMaterial>>,
     ) -> Self {
@@ -40,9 +46,9 @@ impl NpcBundle {
             material: materials.add(material),
             npc: Npc,
             transform: TransformBundle::from_transform(Transform::from_xyz(
-                20.,
+                position.x,
                 GROUND_Y + 5.,
-                20.,
+                position.y,
             )),
             visibility: VisibilityBundle::default(),
         }
@@ -53,7 +59,11 @@ fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    seed: Res<WorldSeed>,
 ) {
-    let bundle = NpcBundle::new(&mut meshes, &mut materials);
-    commands.spawn(bundle);
+    let mut rng = seed.rng(SeedStream::Npcs);
+    let position = Vec2::new(rng.gen_range(10. ..30.), rng.gen_range(10. ..30.));
+    let bundle = NpcBundle::new(position, &mut meshes, &mut materials);
+    // the default cone is 1 tall and centered on its origin
+    commands.spawn((bundle, SnapToTerrain { offset: 0.5 }));
 }
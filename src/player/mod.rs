@@ -1,13 +1,14 @@
 mod controller;
 pub mod view_model;
 pub mod world;
-use crate::world::GROUND_Y;
+use crate::world::{query::SnapToTerrain, GROUND_Y};
 use bevy::{
     pbr::{ExtendedMaterial, MaterialExtension},
     prelude::*,
     render::render_resource::*,
 };
 use bevy_rapier3d::prelude::*;
+pub use controller::LogicalPlayer;
 use controller::FpsControllerPlugin;
 use std::sync::LazyLock;
 use view_model::{spawn_player_hud, PlayerViewModelBundle};
@@ -25,6 +26,7 @@ impl Plugin for PlayerPlugin {
 }
 
 const PLAYER_HEIGHT: f32 = 3.0;
+/// The player is held here until the terrain below it loads, then snapped onto the ground
 const SPAWN_POINT: Vec3 = Vec3::new(0.0, GROUND_Y + 5., 0.0);
 const PLAYER_COLLISION_GROUPS: LazyLock<CollisionGroups> =
     LazyLock::new(|| CollisionGroups::new(Group::GROUP_2, Group::GROUP_1 | Group::GROUP_3));
@@ -47,7 +49,12 @@ impl MaterialExtension for PlayerViewModelExtension {
 
 fn spawn_player(mut commands: Commands) {
     let player_logical_entity_bundle = LogicalPlayerEntityBundle::default();
-    let player_logical_entity = commands.spawn(player_logical_entity_bundle).id();
+    let snap = SnapToTerrain {
+        offset: PLAYER_HEIGHT / 2. + 0.5,
+    };
+    let player_logical_entity = commands
+        .spawn((player_logical_entity_bundle, snap))
+        .id();
 
     commands.spawn(PlayerInWorldBundle::new(player_logical_entity));
     //
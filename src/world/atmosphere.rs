@@ -1,4 +1,4 @@
-use super::icosahedron;
+use super::planet::icosahedron;
 use bevy::{
     pbr::{CascadeShadowConfig, CascadeShadowConfigBuilder, NotShadowCaster},
     prelude::*,
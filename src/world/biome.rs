@@ -0,0 +1,326 @@
+use super::{
+    noise::graph::{NoiseGraph, NoiseKind, NoiseLayer, NoiseNode},
+    rtin::{OffsetSampler, PlaneSampler},
+};
+use bevy::{
+    math::Vec2,
+    prelude::Mesh,
+    render::{
+        mesh::{MeshVertexAttribute, VertexAttributeValues},
+        render_resource::VertexFormat,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Per vertex `Biome` id, the biome whose climate is closest at that vertex
+pub const ATTRIBUTE_BIOME: MeshVertexAttribute =
+    MeshVertexAttribute::new("Terrain_Biome", 723_511_049, VertexFormat::Uint32);
+
+/// Weights below this are dropped, so a biome's heights are only sampled near its own climate
+const MIN_WEIGHT: f32 = 1e-3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Biome {
+    Plains = 0,
+    Hills = 1,
+    Swamp = 2,
+    Mountains = 3,
+}
+
+impl Biome {
+    pub const ALL: [Biome; 4] = [Self::Plains, Self::Hills, Self::Swamp, Self::Mountains];
+
+    pub fn id(&self) -> u32 {
+        *self as u32
+    }
+}
+
+/// A biome's height sampler and where it sits on the climate maps
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BiomeLayer {
+    pub biome: Biome,
+    pub temperature: f32,
+    pub moisture: f32,
+    pub height: NoiseNode,
+}
+
+/// Two low frequency maps place every point in a temperature and moisture climate, and the
+/// biomes whose climate is closest share out its height
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BiomeConfig {
+    pub temperature: NoiseNode,
+    pub moisture: NoiseNode,
+    pub layers: Vec<BiomeLayer>,
+    /// Climate distance over which a biome fades into the next one, larger blends wider
+    pub blend: f32,
+}
+
+impl Default for BiomeConfig {
+    fn default() -> Self {
+        let climate = |seed| NoiseLayer::new(NoiseKind::Simplex, seed).with_frequency(0.002);
+        Self {
+            temperature: climate(11).into(),
+            moisture: climate(12).into(),
+            layers: vec![
+                BiomeLayer {
+                    biome: Biome::Plains,
+                    temperature: 0.3,
+                    moisture: -0.3,
+                    height: NoiseLayer::new(NoiseKind::Fbm, 21)
+                        .with_frequency(0.01)
+                        .with_weight(0.15, 0.)
+                        .with_octaves(3)
+                        .into(),
+                },
+                BiomeLayer {
+                    biome: Biome::Hills,
+                    temperature: -0.2,
+                    moisture: -0.4,
+                    height: NoiseLayer::new(NoiseKind::Billow, 22)
+                        .with_frequency(0.015)
+                        .with_weight(0.4, 0.2)
+                        .with_octaves(3)
+                        .into(),
+                },
+                BiomeLayer {
+                    biome: Biome::Swamp,
+                    temperature: 0.3,
+                    moisture: 0.5,
+                    height: NoiseLayer::new(NoiseKind::Fbm, 23)
+                        .with_frequency(0.03)
+                        .with_weight(0.05, -0.15)
+                        .with_octaves(2)
+                        .into(),
+                },
+                BiomeLayer {
+                    biome: Biome::Mountains,
+                    temperature: -0.5,
+                    moisture: 0.3,
+                    height: NoiseLayer::new(NoiseKind::RidgedMulti, 24)
+                        .with_frequency(0.008)
+                        .with_weight(0.8, 0.4)
+                        .into(),
+                },
+            ],
+            blend: 0.1,
+        }
+    }
+}
+
+impl BiomeConfig {
+    /// See `NoiseNode::reseed`
+    pub fn reseed(&mut self, seed: u32) {
+        self.temperature.reseed(seed);
+        self.moisture.reseed(seed);
+        for layer in self.layers.iter_mut() {
+            layer.height.reseed(seed);
+        }
+    }
+
+    pub fn sampler(&self) -> BiomeSampler {
+        BiomeSampler {
+            temperature: NoiseGraph::new(self.temperature.clone()),
+            moisture: NoiseGraph::new(self.moisture.clone()),
+            layers: self
+                .layers
+                .iter()
+                .map(|layer| {
+                    let climate = Vec2::new(layer.temperature, layer.moisture);
+                    (layer.biome, climate, NoiseGraph::new(layer.height.clone()))
+                })
+                .collect(),
+            blend: self.blend,
+        }
+    }
+}
+
+/// Where the biomes are, apart from the heights. Offset like a `PlaneSampler` when the mesh is
+/// built in local coordinates.
+pub trait BiomeSource: Send + Sync {
+    /// Share of every biome at a point, indexed by `Biome::id`, adding up to 1
+    fn weights(&self, x: f32, y: f32) -> [f32; 4];
+
+    /// The biome with the largest share
+    fn biome(&self, x: f32, y: f32) -> Biome {
+        let weights = self.weights(x, y);
+        Biome::ALL
+            .into_iter()
+            .max_by(|a, b| weights[a.id() as usize].total_cmp(&weights[b.id() as usize]))
+            .unwrap()
+    }
+}
+
+impl<T: BiomeSource + ?Sized> BiomeSource for Arc<T> {
+    fn weights(&self, x: f32, y: f32) -> [f32; 4] {
+        (**self).weights(x, y)
+    }
+}
+
+impl<S: BiomeSource> BiomeSource for OffsetSampler<S> {
+    fn weights(&self, x: f32, y: f32) -> [f32; 4] {
+        self.sampler.weights(x + self.offset.x, y + self.offset.y)
+    }
+}
+
+/// Heights blended from the biome layers, see `BiomeConfig`
+#[derive(Debug)]
+pub struct BiomeSampler {
+    temperature: NoiseGraph,
+    moisture: NoiseGraph,
+    layers: Vec<(Biome, Vec2, NoiseGraph)>,
+    blend: f32,
+}
+
+impl BiomeSampler {
+    pub fn climate(&self, x: f32, y: f32) -> Vec2 {
+        Vec2::new(self.temperature.get(x, y), self.moisture.get(x, y))
+    }
+
+    /// Share of every layer, falling off with how much further its climate is than the closest
+    /// one. The falloff is shifted down by `MIN_WEIGHT` so far layers drop out without a seam.
+    fn layer_weights(&self, x: f32, y: f32) -> Vec<f32> {
+        let climate = self.climate(x, y);
+        let distances: Vec<f32> = self
+            .layers
+            .iter()
+            .map(|(_, center, _)| center.distance(climate))
+            .collect();
+        let closest = distances.iter().copied().fold(f32::INFINITY, f32::min);
+        let mut weights: Vec<f32> = distances
+            .iter()
+            .map(|d| {
+                let falloff = (-(d - closest) / self.blend.max(f32::EPSILON)).exp();
+                ((falloff - MIN_WEIGHT) / (1. - MIN_WEIGHT)).max(0.)
+            })
+            .collect();
+        let total: f32 = weights.iter().sum();
+        weights.iter_mut().for_each(|w| *w /= total);
+        weights
+    }
+}
+
+impl PlaneSampler for BiomeSampler {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        self.layer_weights(x, y)
+            .iter()
+            .zip(self.layers.iter())
+            .filter(|(w, _)| **w > 0.)
+            .map(|(w, (_, _, height))| height.get(x, y) * w)
+            .sum()
+    }
+}
+
+impl BiomeSource for BiomeSampler {
+    fn weights(&self, x: f32, y: f32) -> [f32; 4] {
+        let mut weights = [0.; 4];
+        for (w, (biome, _, _)) in self.layer_weights(x, y).iter().zip(self.layers.iter()) {
+            weights[biome.id() as usize] += w;
+        }
+        weights
+    }
+}
+
+/// Writes `ATTRIBUTE_BIOME`, reading `biomes` at every vertex's x and z
+pub fn apply_biomes(mesh: &mut Mesh, biomes: &dyn BiomeSource) {
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return;
+    };
+    let ids: Vec<u32> = positions
+        .iter()
+        .map(|p| biomes.biome(p[0], p[2]).id())
+        .collect();
+    mesh.insert_attribute(ATTRIBUTE_BIOME, ids);
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::{apply_biomes, Biome, BiomeConfig, BiomeLayer, BiomeSource, ATTRIBUTE_BIOME};
+    use crate::world::{
+        noise::graph::{NoiseKind, NoiseLayer, NoiseNode},
+        rtin::{build_terrain_from_sampler, OffsetSampler, PlaneSampler},
+    };
+    use bevy::{math::Vec2, render::mesh::VertexAttributeValues};
+
+    /// Flat plains at 0 on the warm side and flat mountains at 1 on the cold side
+    fn two_biomes() -> BiomeConfig {
+        BiomeConfig {
+            temperature: NoiseLayer::new(NoiseKind::Simplex, 1)
+                .with_frequency(0.01)
+                .into(),
+            moisture: NoiseNode::Constant(0.),
+            layers: vec![
+                BiomeLayer {
+                    biome: Biome::Plains,
+                    temperature: 0.5,
+                    moisture: 0.,
+                    height: NoiseNode::Constant(0.),
+                },
+                BiomeLayer {
+                    biome: Biome::Mountains,
+                    temperature: -0.5,
+                    moisture: 0.,
+                    height: NoiseNode::Constant(1.),
+                },
+            ],
+            blend: 0.1,
+        }
+    }
+
+    #[test]
+    fn weights_add_up() {
+        let sampler = BiomeConfig::default().sampler();
+        for i in 0..50 {
+            let (x, y) = (i as f32 * 97.3, i as f32 * -41.9);
+            let weights = sampler.weights(x, y);
+            assert!((weights.iter().sum::<f32>() - 1.).abs() < 1e-5);
+            let biome = sampler.biome(x, y);
+            assert!(weights.iter().all(|w| *w <= weights[biome.id() as usize]));
+        }
+    }
+
+    #[test]
+    fn heights_blend_across_borders() {
+        let sampler = two_biomes().sampler();
+        let (mut plains, mut mountains, mut blended) = (0, 0, 0);
+        let mut last = sampler.get(0., 0.);
+        for i in 1..8000 {
+            let x = i as f32 * 0.25;
+            let height = sampler.get(x, 0.);
+            assert!((0. ..=1.).contains(&height));
+            assert!((height - last).abs() < 0.05, "seam at {x}");
+            last = height;
+
+            let biome = sampler.biome(x, 0.);
+            if height == 0. {
+                plains += 1;
+                assert_eq!(biome, Biome::Plains);
+            } else if height == 1. {
+                mountains += 1;
+                assert_eq!(biome, Biome::Mountains);
+            } else {
+                blended += 1;
+            }
+        }
+        assert!(plains > 0 && mountains > 0 && blended > 0);
+    }
+
+    #[test]
+    fn apply_tags_vertices() {
+        let sampler = OffsetSampler::new(two_biomes().sampler(), Vec2::new(300., -120.));
+        let terrain = build_terrain_from_sampler(&sampler, 10., 32., 0.01);
+        let mut mesh = terrain.into_mesh(false, 32.);
+        apply_biomes(&mut mesh, &sampler);
+
+        let Some(VertexAttributeValues::Uint32(ids)) = mesh.attribute(ATTRIBUTE_BIOME) else {
+            panic!("biome ids missing");
+        };
+        assert_eq!(ids.len(), terrain.vertices.len());
+        for (id, v) in ids.iter().zip(terrain.vertices.iter()) {
+            assert_eq!(*id, sampler.biome(v.x, v.z).id());
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::world::{noise::NoiseSampler, rtin::build_terrain_from_sampler, terrain::is_power_of_2};
+use crate::world::{config::TerrainConfig, rtin::build_terrain_from_sampler, terrain::is_power_of_2};
 use bevy::{color::palettes::css::BLACK, prelude::*};
 use super::{
     terrain::{Terrain, TerrainBundle, WORLD_COLLISION_GROUPS},
@@ -6,7 +6,6 @@ use super::{
     GROUND_Y,
 };
 use bevy_rapier3d::prelude::*;
-use noise::{Fbm, Perlin};
 use std::sync::LazyLock;
 
 const CHUNK_SIZE_XYZ: LazyLock<f32> = LazyLock::new(|| {
@@ -47,18 +46,22 @@ impl TerrainBundle {
     }
 }
 
-fn floor_mesh(meshes: &mut ResMut<Assets<Mesh>>, materials: &mut ResMut<Assets<StandardMaterial>>) {
-    let mut noise_func = Fbm::<Perlin>::new(5);
-    noise_func.lacunarity = 0.2;
-    noise_func.frequency = 0.0125;
-    noise_func.octaves = 2;
-    noise_func.persistence = 0.2;
+/// `config` is the loaded `TerrainConfig` asset, so callers rebuilding on its
+/// `AssetEvent::Modified` pick up hot reloads
+fn floor_mesh(
+    config: &TerrainConfig,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+) {
     let size = *LazyLock::force(&CHUNK_SIZE_XYZ);
-    let err_threshold = 0.01;
-    let height_multiplier = 50.;
 
-    let sampler = NoiseSampler::single_layer(noise_func);
-    let terrain = build_terrain_from_sampler (&sampler, height_multiplier, size, err_threshold);
+    let sampler = config.sampler();
+    let terrain = build_terrain_from_sampler(
+        &sampler,
+        config.height_multiplier,
+        size,
+        config.error_threshold,
+    );
     let mesh = terrain.into_mesh(false, size);
 
     let bundle = TerrainBundle::new(mesh, meshes, materials);
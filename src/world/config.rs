@@ -0,0 +1,370 @@
+use super::{
+    biome::{BiomeConfig, BiomeSampler, BiomeSource},
+    jobs::{TerrainBuildRequest, TerrainBuildTask},
+    noise::graph::{NoiseGraph, NoiseKind, NoiseLayer, NoiseNode},
+    rtin::{coloring::TerrainColoring, PlaneSampler},
+    seed::{SeedStream, WorldSeed},
+    streaming::{LoadedChunks, TerrainChunk, TerrainStreaming},
+    terrain::{is_power_of_2, TerrainColliderMode},
+    GROUND_Y,
+};
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    scene::ron,
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, sync::Arc};
+
+/// Loaded at startup, relative to the assets folder
+pub const TERRAIN_CONFIG_PATH: &str = "terrain/default.terrain.ron";
+
+pub struct TerrainConfigPlugin;
+
+impl Plugin for TerrainConfigPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<TerrainConfig>()
+            .init_asset_loader::<TerrainConfigLoader>()
+            .add_systems(Startup, load_terrain_config);
+    }
+}
+
+/// Everything the terrain is generated from, read from a `.terrain.ron` file
+#[derive(Asset, TypePath, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerrainConfig {
+    pub sampler: NoiseNode,
+    /// Side of a streamed chunk, or of the whole terrain when it is built as one mesh. A power
+    /// of 2, checked when the config loads.
+    pub size: f32,
+    pub height_multiplier: f32,
+    /// Threshold of the closest LOD band, every further band is 4 times coarser
+    pub error_threshold: f32,
+    /// Splat layers the terrain is colored with, `None` leaves it plain
+    pub material: Option<TerrainColoring>,
+    /// Depth of the skirts under streamed chunk borders, `None` builds them without
+    pub skirt_depth: Option<f32>,
+    /// Heights come from the biomes instead of `sampler` when set
+    pub biomes: Option<BiomeConfig>,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            sampler: NoiseLayer {
+                kind: NoiseKind::Fbm,
+                seed: 5,
+                frequency: 0.0125,
+                octaves: 2,
+                lacunarity: 0.2,
+                persistence: 0.2,
+                ..Default::default()
+            }
+            .into(),
+            size: 64.,
+            height_multiplier: 50.,
+            error_threshold: 0.005,
+            material: Some(TerrainColoring::default()),
+            skirt_depth: Some(4.),
+            biomes: None,
+        }
+    }
+}
+
+impl TerrainConfig {
+    /// The same config with every noise layer's seed offset by the world's terrain seed
+    pub fn with_seed(&self, seed: WorldSeed) -> Self {
+        let mut config = self.clone();
+        let seed = seed.derive_u32(SeedStream::TerrainNoise);
+        config.sampler.reseed(seed);
+        if let Some(biomes) = config.biomes.as_mut() {
+            biomes.reseed(seed);
+        }
+        config
+    }
+
+    /// The biome blend when biomes are set, the plain noise graph otherwise
+    pub fn sampler(&self) -> Arc<dyn PlaneSampler> {
+        match self.biome_sampler() {
+            Some(biomes) => biomes,
+            None => Arc::new(NoiseGraph::new(self.sampler.clone())),
+        }
+    }
+
+    pub fn biome_sampler(&self) -> Option<Arc<BiomeSampler>> {
+        self.biomes
+            .as_ref()
+            .map(|biomes| Arc::new(biomes.sampler()))
+    }
+
+    pub fn streaming(&self) -> TerrainStreaming {
+        let biomes = self.biome_sampler();
+        let mut streaming = match &biomes {
+            Some(biomes) => TerrainStreaming::new(biomes.clone()),
+            None => TerrainStreaming::new(self.sampler()),
+        };
+        streaming.biomes = biomes;
+        streaming.chunk_size = self.size;
+        streaming.height_multiplier = self.height_multiplier;
+        streaming.coloring = self.material.clone();
+        streaming.skirt_depth = self.skirt_depth;
+        let mut error_threshold = self.error_threshold;
+        for band in streaming.lod_bands.iter_mut() {
+            band.error_threshold = error_threshold;
+            error_threshold *= 4.;
+        }
+        streaming
+    }
+
+    /// A single `size` x `size` terrain at the origin
+    pub fn terrain_request(&self) -> TerrainBuildRequest {
+        let biomes = self.biome_sampler();
+        TerrainBuildRequest {
+            sampler: match biomes.clone() {
+                Some(biomes) => biomes,
+                None => self.sampler(),
+            },
+            height_multiplier: self.height_multiplier,
+            width: self.size,
+            depth: self.size,
+            error_threshold: self.error_threshold,
+            lock_edges: false,
+            collider_mode: TerrainColliderMode::Heightfield,
+            coloring: self.material.clone(),
+            skirt_depth: None,
+            biomes: biomes.map(|biomes| biomes as Arc<dyn BiomeSource>),
+            transform: Transform::from_xyz(0., GROUND_Y, 0.),
+            name: Name::new("Terrain"),
+        }
+    }
+}
+
+/// The config the world is generated from
+#[derive(Resource, Debug, Clone)]
+pub struct TerrainConfigHandle(pub Handle<TerrainConfig>);
+
+impl TerrainConfigHandle {
+    /// Whether `event` means the config finished loading or was reloaded
+    pub fn changed(&self, event: &AssetEvent<TerrainConfig>) -> bool {
+        event.is_added(&self.0) || event.is_modified(&self.0)
+    }
+}
+
+impl TerrainConfig {
+    /// Parses a `.terrain.ron` file and checks what serde can't
+    pub fn from_ron_bytes(bytes: &[u8]) -> Result<Self, TerrainConfigError> {
+        let config: Self = ron::de::from_bytes(bytes)?;
+        if !is_power_of_2(config.size) {
+            return Err(TerrainConfigError::SizeNotPowerOfTwo(config.size));
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Default)]
+pub struct TerrainConfigLoader;
+
+#[derive(Debug)]
+pub enum TerrainConfigError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    SizeNotPowerOfTwo(f32),
+}
+
+impl fmt::Display for TerrainConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "could not read terrain config: {err}"),
+            Self::Ron(err) => write!(f, "could not parse terrain config: {err}"),
+            Self::SizeNotPowerOfTwo(size) => {
+                write!(f, "terrain size must be a power of 2, got {size}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TerrainConfigError {}
+
+impl From<std::io::Error> for TerrainConfigError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<ron::error::SpannedError> for TerrainConfigError {
+    fn from(value: ron::error::SpannedError) -> Self {
+        Self::Ron(value)
+    }
+}
+
+impl AssetLoader for TerrainConfigLoader {
+    type Asset = TerrainConfig;
+    type Settings = ();
+    type Error = TerrainConfigError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<TerrainConfig, TerrainConfigError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).await?;
+        TerrainConfig::from_ron_bytes(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["terrain.ron"]
+    }
+}
+
+pub fn load_terrain_config(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(TerrainConfigHandle(asset_server.load(TERRAIN_CONFIG_PATH)));
+}
+
+/// Replaces `TerrainStreaming` whenever the config is loaded or edited, seeded from the
+/// `WorldSeed` and keeping the brush strokes. Loaded chunks are rebuilt in place and keep
+/// their old mesh until the new one is ready, unless the chunk size changed and they no
+/// longer line up.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_terrain_config(
+    mut commands: Commands,
+    handle: Res<TerrainConfigHandle>,
+    configs: Res<Assets<TerrainConfig>>,
+    seed: Res<WorldSeed>,
+    mut events: EventReader<AssetEvent<TerrainConfig>>,
+    streaming: Option<Res<TerrainStreaming>>,
+    mut loaded: ResMut<LoadedChunks>,
+    chunk_q: Query<&TerrainChunk>,
+) {
+    if !events.read().any(|event| handle.changed(event)) {
+        return;
+    }
+    let Some(config) = configs.get(&handle.0) else {
+        return;
+    };
+
+    let mut next = config.with_seed(*seed).streaming();
+    let mut keep_chunks = false;
+    if let Some(current) = streaming {
+        next.deformation = current.deformation.clone();
+        keep_chunks = current.chunk_size == next.chunk_size;
+    }
+
+    if keep_chunks {
+        for entity in loaded.0.values() {
+            let Ok(chunk) = chunk_q.get(*entity) else {
+                continue;
+            };
+            let task = TerrainBuildTask::spawn(next.chunk_request(*chunk));
+            commands.entity(*entity).insert(task);
+        }
+    } else {
+        for (_, entity) in loaded.0.drain() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    commands.insert_resource(next);
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::{
+        apply_terrain_config, TerrainConfig, TerrainConfigError, TerrainConfigHandle,
+        TerrainConfigPlugin,
+    };
+    use crate::world::{
+        biome::BiomeConfig,
+        rtin::PlaneSampler,
+        seed::WorldSeed,
+        streaming::{LoadedChunks, TerrainStreaming},
+    };
+    use bevy::{prelude::*, scene::ron};
+    use std::time::Duration;
+
+    #[test]
+    fn default_asset_matches_default() {
+        let config = TerrainConfig::from_ron_bytes(include_bytes!(
+            "../../assets/terrain/default.terrain.ron"
+        ))
+        .unwrap();
+        let default = TerrainConfig::default();
+        assert_eq!(config.sampler, default.sampler);
+        assert_eq!(config.size, default.size);
+        assert_eq!(config.height_multiplier, default.height_multiplier);
+        assert_eq!(config.error_threshold, default.error_threshold);
+        assert_eq!(config.skirt_depth, default.skirt_depth);
+        assert_eq!(config.biomes, default.biomes);
+
+        // colors are written out rounded
+        let (material, default) = (config.material.unwrap(), default.material.unwrap());
+        for (layer, default) in material.layers.iter().zip(default.layers.iter()) {
+            assert_eq!(layer.height, default.height);
+            assert_eq!(layer.slope, default.slope);
+            assert!((layer.color.red - default.color.red).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn size_must_be_power_of_2() {
+        assert!(matches!(
+            TerrainConfig::from_ron_bytes(b"(size: 100.)"),
+            Err(TerrainConfigError::SizeNotPowerOfTwo(size)) if size == 100.
+        ));
+        assert!(TerrainConfig::from_ron_bytes(b"(size: 64.)").is_ok());
+    }
+
+    #[test]
+    fn streaming_bands_from_threshold() {
+        let config = TerrainConfig {
+            error_threshold: 0.01,
+            size: 32.,
+            ..Default::default()
+        };
+        let streaming = config.streaming();
+        assert_eq!(streaming.chunk_size, 32.);
+        let thresholds: Vec<f32> = streaming
+            .lod_bands
+            .iter()
+            .map(|band| band.error_threshold)
+            .collect();
+        assert_eq!(thresholds, vec![0.01, 0.04, 0.16]);
+    }
+
+    #[test]
+    fn biomes_replace_sampler() {
+        let config = TerrainConfig {
+            biomes: Some(BiomeConfig::default()),
+            ..Default::default()
+        };
+        let text = ron::ser::to_string(&config).unwrap();
+        let parsed: TerrainConfig = ron::de::from_str(&text).unwrap();
+        assert_eq!(parsed.biomes, config.biomes);
+
+        let biomes = BiomeConfig::default().sampler();
+        assert_eq!(config.sampler().get(12., 34.), biomes.get(12., 34.));
+        assert!(config.streaming().biomes.is_some());
+        assert!(config.terrain_request().biomes.is_some());
+        assert!(TerrainConfig::default().streaming().biomes.is_none());
+    }
+
+    #[test]
+    fn loaded_config_starts_streaming() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default(), TerrainConfigPlugin))
+            .init_resource::<LoadedChunks>()
+            .insert_resource(WorldSeed(1))
+            .add_systems(Update, apply_terrain_config);
+
+        for _ in 0..200 {
+            app.update();
+            if app.world().contains_resource::<TerrainStreaming>() {
+                let streaming = app.world().resource::<TerrainStreaming>();
+                assert_eq!(streaming.chunk_size, TerrainConfig::default().size);
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("terrain config never loaded");
+    }
+}
@@ -0,0 +1,324 @@
+use super::{
+    jobs::TerrainBuildTask,
+    rtin::PlaneSampler,
+    streaming::{stream_terrain_chunks, LoadedChunks, TerrainChunk, TerrainStreaming},
+};
+use bevy::prelude::*;
+use std::{collections::HashMap, sync::Arc};
+
+pub struct TerrainDeformationPlugin;
+
+impl Plugin for TerrainDeformationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DeformTerrain>().add_systems(
+            Update,
+            deform_terrain
+                .after(stream_terrain_chunks)
+                .run_if(resource_exists::<TerrainStreaming>),
+        );
+    }
+}
+
+/// What a brush does to the ground inside its radius, amounts are in world units
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TerrainBrush {
+    Crater {
+        depth: f32,
+    },
+    Raise {
+        height: f32,
+    },
+    /// Pulls the ground towards a world height, relative to the terrain's origin
+    Flatten {
+        height: f32,
+    },
+    /// Moves every point towards the average of its neighbours, `strength` in [0, 1]
+    Smooth {
+        strength: f32,
+    },
+}
+
+/// A brush applied around a world XZ position. The effect fades out towards `radius`.
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct DeformTerrain {
+    pub brush: TerrainBrush,
+    pub center: Vec2,
+    pub radius: f32,
+}
+
+/// Side of the square tiles a `HeightLayer` stores its offsets in, in grid points
+const LAYER_TILE: i32 = 32;
+
+/// Height offsets in world units on the integer grid the terrain is built on, stacked on top
+/// of a base sampler. Points never touched by a brush cost nothing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeightLayer {
+    /// Offsets by tile. Clones share every tile, a stroke only copies the tiles it changes.
+    tiles: HashMap<IVec2, Arc<HashMap<IVec2, f32>>>,
+}
+
+impl HeightLayer {
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    pub fn offset_at(&self, point: IVec2) -> f32 {
+        self.tiles
+            .get(&tile_of(point))
+            .and_then(|tile| tile.get(&point))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Bilinearly interpolated offset between grid points
+    pub fn offset(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let p = IVec2::new(x0 as i32, y0 as i32);
+
+        let top = self.offset_at(p) * (1. - tx) + self.offset_at(p + IVec2::X) * tx;
+        let bottom = self.offset_at(p + IVec2::Y) * (1. - tx) + self.offset_at(p + IVec2::ONE) * tx;
+        top * (1. - ty) + bottom * ty
+    }
+
+    /// Applies `stroke` on top of `base` scaled by `height_multiplier` and returns the
+    /// smallest rectangle holding every grid point it changed, `None` if it changed nothing
+    pub fn apply(
+        &mut self,
+        base: &impl PlaneSampler,
+        height_multiplier: f32,
+        stroke: &DeformTerrain,
+    ) -> Option<IRect> {
+        let height = |layer: &Self, p: IVec2| {
+            base.get(p.x as f32, p.y as f32) * height_multiplier + layer.offset_at(p)
+        };
+
+        let min = (stroke.center - stroke.radius).ceil().as_ivec2();
+        let max = (stroke.center + stroke.radius).floor().as_ivec2();
+        let mut changes = vec![];
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                let p = IVec2::new(x, y);
+                let distance = p.as_vec2().distance(stroke.center);
+                if distance >= stroke.radius {
+                    continue;
+                }
+                let falloff = (1. - (distance / stroke.radius).powi(2)).powi(2);
+
+                let change = match stroke.brush {
+                    TerrainBrush::Crater { depth } => -depth * falloff,
+                    TerrainBrush::Raise { height } => height * falloff,
+                    TerrainBrush::Flatten { height: target } => {
+                        (target - height(self, p)) * falloff
+                    }
+                    TerrainBrush::Smooth { strength } => {
+                        let neighbours = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+                        let average =
+                            neighbours.iter().map(|n| height(self, p + *n)).sum::<f32>() / 4.;
+                        (average - height(self, p)) * strength * falloff
+                    }
+                };
+                if change != 0. {
+                    changes.push((p, change));
+                }
+            }
+        }
+
+        // every point is worked out from the heights before the stroke, then written at once
+        let mut changed: Option<IRect> = None;
+        for (p, change) in changes {
+            let tile = Arc::make_mut(self.tiles.entry(tile_of(p)).or_default());
+            *tile.entry(p).or_default() += change;
+            changed = Some(match changed {
+                Some(rect) => rect.union_point(p),
+                None => IRect::from_corners(p, p),
+            });
+        }
+        changed
+    }
+}
+
+fn tile_of(point: IVec2) -> IVec2 {
+    point.div_euclid(IVec2::splat(LAYER_TILE))
+}
+
+/// The base sampler with a `HeightLayer` stacked on top. The layer is in world units, so it
+/// is divided by the multiplier the terrain is built with.
+#[derive(Debug, Clone)]
+pub struct DeformedSampler<S> {
+    pub sampler: S,
+    pub layer: Arc<HeightLayer>,
+    pub height_multiplier: f32,
+}
+
+impl<S: PlaneSampler> PlaneSampler for DeformedSampler<S> {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        self.sampler.get(x, y) + self.layer.offset(x, y) / self.height_multiplier
+    }
+}
+
+/// Applies brush strokes to the streamed terrain and rebuilds the chunks they touched. Chunks
+/// are rebuilt whole, since RTIN picks its triangles over the entire chunk and a patched
+/// region would no longer match its borders. Builds already running keep the layer they
+/// started with, the rebuild replaces them.
+pub fn deform_terrain(
+    mut commands: Commands,
+    mut streaming: ResMut<TerrainStreaming>,
+    loaded: Res<LoadedChunks>,
+    mut events: EventReader<DeformTerrain>,
+    chunk_q: Query<&TerrainChunk>,
+) {
+    let mut touched = vec![];
+    for stroke in events.read() {
+        let streaming = streaming.as_mut();
+        let layer = Arc::make_mut(&mut streaming.deformation);
+        if let Some(rect) = layer.apply(&streaming.sampler, streaming.height_multiplier, stroke) {
+            touched.extend(streaming.chunks_overlapping(rect));
+        }
+    }
+    touched.sort_by_key(|c| (c.x, c.y));
+    touched.dedup();
+
+    for coord in touched {
+        let Some(entity) = loaded.0.get(&coord) else {
+            continue;
+        };
+        let Ok(chunk) = chunk_q.get(*entity) else {
+            continue;
+        };
+        let task = TerrainBuildTask::spawn(streaming.chunk_request(*chunk));
+        commands.entity(*entity).insert(task);
+    }
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::{DeformTerrain, DeformedSampler, HeightLayer, TerrainBrush};
+    use crate::world::rtin::PlaneSampler;
+    use bevy::math::{IRect, IVec2, Vec2};
+    use std::sync::Arc;
+
+    struct Flat(f32);
+
+    impl PlaneSampler for Flat {
+        fn get(&self, _x: f32, _y: f32) -> f32 {
+            self.0
+        }
+    }
+
+    fn stroke(brush: TerrainBrush) -> DeformTerrain {
+        DeformTerrain {
+            brush,
+            center: Vec2::new(10., 10.),
+            radius: 3.,
+        }
+    }
+
+    #[test]
+    fn crater_digs_inside_radius() {
+        let mut layer = HeightLayer::default();
+        let rect = layer
+            .apply(&Flat(0.), 1., &stroke(TerrainBrush::Crater { depth: 2. }))
+            .unwrap();
+
+        assert_eq!(rect, IRect::new(8, 8, 12, 12));
+        assert_eq!(layer.offset_at(IVec2::new(10, 10)), -2.);
+        assert!(layer.offset_at(IVec2::new(11, 10)) > -2.);
+        assert_eq!(layer.offset_at(IVec2::new(13, 10)), 0.);
+        assert_eq!(
+            layer.offset(10.5, 10.),
+            layer.offset_at(IVec2::new(11, 10)) / 2. - 1.
+        );
+    }
+
+    #[test]
+    fn flatten_reaches_target() {
+        let mut layer = HeightLayer::default();
+        let base = Flat(0.5);
+        layer.apply(&base, 4., &stroke(TerrainBrush::Flatten { height: 5. }));
+        // base height is 0.5 * 4 at every point
+        assert_eq!(layer.offset_at(IVec2::new(10, 10)), 3.);
+
+        let sampler = DeformedSampler {
+            sampler: base,
+            layer: Arc::new(layer),
+            height_multiplier: 4.,
+        };
+        assert_eq!(sampler.get(10., 10.) * 4., 5.);
+        assert_eq!(sampler.get(20., 10.) * 4., 2.);
+    }
+
+    #[test]
+    fn smooth_evens_out_spike() {
+        let mut layer = HeightLayer::default();
+        let spike = DeformTerrain {
+            radius: 1.,
+            ..stroke(TerrainBrush::Raise { height: 4. })
+        };
+        layer.apply(&Flat(0.), 1., &spike);
+        assert_eq!(layer.offset_at(IVec2::new(10, 10)), 4.);
+
+        layer.apply(
+            &Flat(0.),
+            1.,
+            &stroke(TerrainBrush::Smooth { strength: 1. }),
+        );
+        assert_eq!(layer.offset_at(IVec2::new(10, 10)), 0.);
+        let falloff = (1. - 1. / 9f32).powi(2);
+        assert!((layer.offset_at(IVec2::new(11, 10)) - falloff).abs() < 1e-6);
+    }
+
+    #[test]
+    fn strokes_only_copy_touched_tiles() {
+        let mut layer = HeightLayer::default();
+        let raise = stroke(TerrainBrush::Raise { height: 1. });
+        let far = DeformTerrain {
+            center: Vec2::new(100., 100.),
+            ..raise
+        };
+        layer.apply(&Flat(0.), 1., &raise);
+        layer.apply(&Flat(0.), 1., &far);
+
+        let before = layer.clone();
+        layer.apply(&Flat(0.), 1., &far);
+        assert!(Arc::ptr_eq(
+            &layer.tiles[&IVec2::ZERO],
+            &before.tiles[&IVec2::ZERO]
+        ));
+        assert!(!Arc::ptr_eq(
+            &layer.tiles[&IVec2::splat(3)],
+            &before.tiles[&IVec2::splat(3)]
+        ));
+        assert_eq!(before.offset_at(IVec2::splat(100)), 1.);
+        assert_eq!(layer.offset_at(IVec2::splat(100)), 2.);
+
+        // strokes across tile borders, negative ones included
+        let mut layer = HeightLayer::default();
+        let origin = DeformTerrain {
+            center: Vec2::ZERO,
+            ..raise
+        };
+        layer.apply(&Flat(0.), 1., &origin);
+        assert_eq!(layer.tiles.len(), 4);
+        assert_eq!(
+            layer.offset_at(IVec2::new(-1, 0)),
+            layer.offset_at(IVec2::X)
+        );
+        assert_eq!(
+            layer.offset_at(IVec2::new(0, -2)),
+            layer.offset_at(IVec2::new(2, 0))
+        );
+    }
+
+    #[test]
+    fn untouched_outside_radius() {
+        let mut layer = HeightLayer::default();
+        let far = DeformTerrain {
+            brush: TerrainBrush::Raise { height: 1. },
+            center: Vec2::new(0.5, 0.5),
+            radius: 0.5,
+        };
+        assert_eq!(layer.apply(&Flat(0.), 1., &far), None);
+        assert!(layer.is_empty());
+    }
+}
@@ -0,0 +1,206 @@
+use super::HeightFilter;
+use crate::world::heightmap::HeightGrid;
+use bevy::math::Vec2;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Droplet based hydraulic erosion. Every droplet starts at a random point, runs downhill
+/// picking up sediment while it speeds up and drops it again where it slows down or the
+/// ground rises, until it evaporates or leaves the grid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HydraulicErosion {
+    /// Number of droplets simulated
+    pub iterations: usize,
+    /// Droplets start at the same points for the same seed, so the output is reproducible
+    pub seed: u64,
+    /// Fraction of the droplet's free capacity it erodes per step
+    pub erosion: f32,
+    /// Fraction of the sediment over capacity it deposits per step
+    pub deposition: f32,
+    /// Fraction of the water lost per step
+    pub evaporation: f32,
+    /// How much a droplet keeps its direction instead of following the slope, in [0, 1]
+    pub inertia: f32,
+    /// Sediment a droplet can carry per unit of height dropped, speed and water
+    pub capacity: f32,
+    pub min_capacity: f32,
+    pub gravity: f32,
+    /// Steps before a droplet is dropped
+    pub max_lifetime: usize,
+}
+
+impl Default for HydraulicErosion {
+    fn default() -> Self {
+        Self {
+            iterations: 50_000,
+            seed: 0,
+            erosion: 0.3,
+            deposition: 0.3,
+            evaporation: 0.01,
+            inertia: 0.05,
+            capacity: 4.,
+            min_capacity: 0.01,
+            gravity: 4.,
+            max_lifetime: 30,
+        }
+    }
+}
+
+impl HeightFilter for HydraulicErosion {
+    fn apply(&self, grid: &mut HeightGrid) {
+        if grid.width < 2 || grid.depth < 2 {
+            return;
+        }
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let max = Vec2::new((grid.width - 1) as f32, (grid.depth - 1) as f32);
+
+        for _ in 0..self.iterations {
+            let start = Vec2::new(rng.gen_range(0. ..max.x), rng.gen_range(0. ..max.y));
+            self.simulate_droplet(grid, start, max);
+        }
+    }
+}
+
+impl HydraulicErosion {
+    fn simulate_droplet(&self, grid: &mut HeightGrid, start: Vec2, max: Vec2) {
+        let mut position = start;
+        let mut direction = Vec2::ZERO;
+        let mut speed = 1.;
+        let mut water = 1.;
+        let mut sediment = 0.;
+
+        for _ in 0..self.max_lifetime {
+            let (height, gradient) = height_and_gradient(grid, position);
+            direction =
+                (direction * self.inertia - gradient * (1. - self.inertia)).normalize_or_zero();
+            if direction == Vec2::ZERO {
+                break;
+            }
+
+            let next = position + direction;
+            if next.x < 0. || next.y < 0. || next.x >= max.x || next.y >= max.y {
+                break;
+            }
+            let delta_height = height_and_gradient(grid, next).0 - height;
+
+            let capacity = (-delta_height * speed * water * self.capacity).max(self.min_capacity);
+            if delta_height > 0. || sediment > capacity {
+                // fill the pit it is climbing out of, or shed what it can no longer carry
+                let amount = if delta_height > 0. {
+                    delta_height.min(sediment)
+                } else {
+                    (sediment - capacity) * self.deposition
+                };
+                sediment -= amount;
+                spread(grid, position, amount);
+            } else {
+                // never dig deeper than the drop to the next point, which would leave holes
+                let amount = ((capacity - sediment) * self.erosion).min(-delta_height);
+                sediment += amount;
+                spread(grid, position, -amount);
+            }
+
+            speed = (speed * speed - delta_height * self.gravity).max(0.).sqrt();
+            water *= 1. - self.evaporation;
+            position = next;
+        }
+    }
+}
+
+/// Bilinear height and its gradient inside the cell containing `position`
+fn height_and_gradient(grid: &HeightGrid, position: Vec2) -> (f32, Vec2) {
+    let (x, y) = (position.x as usize, position.y as usize);
+    let (u, v) = (position.x - x as f32, position.y - y as f32);
+    let nw = grid.get(x, y);
+    let ne = grid.get(x + 1, y);
+    let sw = grid.get(x, y + 1);
+    let se = grid.get(x + 1, y + 1);
+
+    let gradient = Vec2::new(
+        (ne - nw) * (1. - v) + (se - sw) * v,
+        (sw - nw) * (1. - u) + (se - ne) * u,
+    );
+    let height = nw * (1. - u) * (1. - v) + ne * u * (1. - v) + sw * (1. - u) * v + se * u * v;
+    (height, gradient)
+}
+
+/// Adds `amount` to the four corners of the cell containing `position`, weighted by distance
+fn spread(grid: &mut HeightGrid, position: Vec2, amount: f32) {
+    let (x, y) = (position.x as usize, position.y as usize);
+    let (u, v) = (position.x - x as f32, position.y - y as f32);
+    for (cx, cy, weight) in [
+        (x, y, (1. - u) * (1. - v)),
+        (x + 1, y, u * (1. - v)),
+        (x, y + 1, (1. - u) * v),
+        (x + 1, y + 1, u * v),
+    ] {
+        let height = grid.get(cx, cy);
+        grid.set(cx, cy, height + amount * weight);
+    }
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::HydraulicErosion;
+    use crate::world::{
+        erosion::{ErodedSampler, HeightFilter},
+        heightmap::HeightGrid,
+        noise::NoiseSampler,
+        rtin::PlaneSampler,
+    };
+    use noise::{Fbm, Perlin};
+
+    fn erosion(seed: u64) -> HydraulicErosion {
+        HydraulicErosion {
+            iterations: 2_000,
+            seed,
+            ..Default::default()
+        }
+    }
+
+    fn sampler() -> NoiseSampler {
+        let mut noise = Fbm::<Perlin>::new(3);
+        noise.frequency = 0.05;
+        NoiseSampler::single_layer(noise)
+    }
+
+    #[test]
+    fn deterministic_for_seed() {
+        let a = ErodedSampler::new(sampler(), 48, 32, &erosion(7));
+        let b = ErodedSampler::new(sampler(), 48, 32, &erosion(7));
+        let c = ErodedSampler::new(sampler(), 48, 32, &erosion(8));
+        assert_eq!(a.grid(), b.grid());
+        assert_ne!(a.grid(), c.grid());
+    }
+
+    #[test]
+    fn erodes_terrain() {
+        let no_droplets = HydraulicErosion {
+            iterations: 0,
+            ..erosion(7)
+        };
+        let original = ErodedSampler::new(sampler(), 48, 32, &no_droplets);
+        let eroded = ErodedSampler::new(sampler(), 48, 32, &erosion(7));
+        assert_ne!(original.grid(), eroded.grid());
+        assert!(eroded.grid().data.iter().all(|h| h.is_finite()));
+
+        // rasterized heights are served back unchanged without any droplets
+        let inner = sampler();
+        assert_eq!(original.get(10., 20.), inner.get(10., 20.));
+        // and outside the eroded area the inner sampler is used
+        assert_eq!(eroded.get(60., 5.), inner.get(60., 5.));
+    }
+
+    #[test]
+    fn droplet_carves_slope() {
+        // a plane falling towards +x, droplets run down it and wear it away
+        let (width, depth) = (32, 16);
+        let data = (0..width * depth)
+            .map(|i| -((i % width) as f32) * 0.5)
+            .collect();
+        let mut grid = HeightGrid::new(width, depth, data);
+        let before: f32 = grid.data.iter().sum();
+        erosion(1).apply(&mut grid);
+        let after: f32 = grid.data.iter().sum();
+        assert!(after < before);
+    }
+}
@@ -0,0 +1,80 @@
+pub mod hydraulic;
+pub mod rivers;
+pub mod thermal;
+use super::{heightmap::HeightGrid, rtin::PlaneSampler};
+pub use hydraulic::HydraulicErosion;
+pub use rivers::RiverNetwork;
+pub use thermal::ThermalErosion;
+
+/// The 8 neighbours of a grid cell as `(dx, dy)`, the first 4 come before the cell in row major
+/// order and the last 4 after it
+pub(super) const NEIGHBOURS: [(isize, isize); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// A pass over a rasterized height grid, like an erosion simulation
+pub trait HeightFilter: Send + Sync {
+    fn apply(&self, grid: &mut HeightGrid);
+}
+
+/// Runs the first filter, then the second on its output
+impl<A: HeightFilter, B: HeightFilter> HeightFilter for (A, B) {
+    fn apply(&self, grid: &mut HeightGrid) {
+        self.0.apply(grid);
+        self.1.apply(grid);
+    }
+}
+
+/// Runs every filter in order
+impl HeightFilter for Vec<Box<dyn HeightFilter>> {
+    fn apply(&self, grid: &mut HeightGrid) {
+        for filter in self {
+            filter.apply(grid);
+        }
+    }
+}
+
+/// Rasterizes the inner sampler over `[0, width] x [0, depth]` at one sample per world unit,
+/// runs a filter over the grid and serves the filtered heights back. Outside of that area the
+/// inner sampler is returned untouched.
+#[derive(Debug, Clone)]
+pub struct ErodedSampler<S> {
+    pub sampler: S,
+    grid: HeightGrid,
+}
+
+impl<S: PlaneSampler> ErodedSampler<S> {
+    pub fn new(sampler: S, width: usize, depth: usize, filter: &impl HeightFilter) -> Self {
+        let mut data = Vec::with_capacity((width + 1) * (depth + 1));
+        for y in 0..=depth {
+            for x in 0..=width {
+                data.push(sampler.get(x as f32, y as f32));
+            }
+        }
+        let mut grid = HeightGrid::new(width + 1, depth + 1, data);
+        filter.apply(&mut grid);
+        Self { sampler, grid }
+    }
+
+    pub fn grid(&self) -> &HeightGrid {
+        &self.grid
+    }
+}
+
+impl<S: PlaneSampler> PlaneSampler for ErodedSampler<S> {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        let max_x = (self.grid.width - 1) as f32;
+        let max_y = (self.grid.depth - 1) as f32;
+        if x < 0. || y < 0. || x > max_x || y > max_y {
+            return self.sampler.get(x, y);
+        }
+        self.grid.bilinear(x, y)
+    }
+}
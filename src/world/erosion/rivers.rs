@@ -0,0 +1,570 @@
+use super::{HeightFilter, NEIGHBOURS};
+use crate::world::heightmap::HeightGrid;
+use bevy::math::{
+    cubic_splines::{CubicCardinalSpline, CubicCurve, CubicGenerator},
+    Vec2, Vec3, Vec3Swizzles,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{cmp::Ordering, collections::BinaryHeap, f32::consts::SQRT_2};
+
+/// Rise added per cell while filling pits for flow routing, so filled flats still drain
+const FILL_STEP: f32 = 1e-5;
+
+/// Rivers running downhill from random high points. Every river follows the steepest way down
+/// until it leaves the grid, reaches `sea_level` or runs into an earlier river, which it then
+/// joins as a tributary. Pits are filled before routing, so rivers flow straight through them
+/// and their channels are cut deep enough to keep going downhill.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiverNetwork {
+    /// Sources picked, the highest is traced first
+    pub sources: usize,
+    /// Sources are picked at the same points for the same seed
+    pub seed: u64,
+    /// Sources are only picked above this fraction of the way from the lowest to the highest
+    /// point of the grid
+    pub min_source_height: f32,
+    /// Rivers running through fewer cells are dropped, and so are single cells whatever this
+    /// is, so every river has a spline
+    pub min_length: usize,
+    /// Rivers end once their water drops below this height
+    pub sea_level: Option<f32>,
+    /// Channel width in grid cells, nothing is carved where it is 0 or less
+    pub width: RiverProfile,
+    /// Channel depth in grid height units
+    pub depth: RiverProfile,
+}
+
+/// A channel dimension, growing with the square root of the water running through the channel
+/// from `source` at a spring to `mouth` where the whole network's largest river ends
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiverProfile {
+    pub source: f32,
+    pub mouth: f32,
+}
+
+/// Positions and channel sizes are in grid space, like the `HeightGrid` the river was traced
+/// over: one unit per cell and unscaled heights. `to_world` places it over a terrain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct River {
+    /// From the source downstream, one per grid cell
+    pub points: Vec<RiverPoint>,
+    /// Index of the river this one flows into at its last point
+    pub tributary_of: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiverPoint {
+    /// Grid x, water surface height and grid y. The surface never rises downstream.
+    pub position: Vec3,
+    /// Cells drained through this point, tributaries included
+    pub discharge: f32,
+    pub width: f32,
+    pub depth: f32,
+}
+
+impl Default for RiverNetwork {
+    fn default() -> Self {
+        Self {
+            sources: 24,
+            seed: 0,
+            min_source_height: 0.6,
+            min_length: 16,
+            sea_level: None,
+            width: RiverProfile {
+                source: 2.,
+                mouth: 8.,
+            },
+            depth: RiverProfile {
+                source: 0.02,
+                mouth: 0.08,
+            },
+        }
+    }
+}
+
+/// Only carves the channels, the rivers themselves come from `generate`
+impl HeightFilter for RiverNetwork {
+    fn apply(&self, grid: &mut HeightGrid) {
+        self.generate(grid);
+    }
+}
+
+impl RiverNetwork {
+    /// Traces the rivers and carves them into `grid`
+    pub fn generate(&self, grid: &mut HeightGrid) -> Vec<River> {
+        let rivers = self.trace(grid);
+        carve(grid, &rivers);
+        rivers
+    }
+
+    /// Rivers over `grid`, without changing it
+    pub fn trace(&self, grid: &HeightGrid) -> Vec<River> {
+        if grid.width < 3 || grid.depth < 3 {
+            return vec![];
+        }
+        let filled = fill_pits(grid);
+        // river and point index of every cell a river runs through
+        let mut owner: Vec<Option<(usize, usize)>> = vec![None; grid.data.len()];
+        let mut rivers = Vec::<River>::new();
+
+        for source in self.pick_sources(grid) {
+            if owner[source].is_some() {
+                continue;
+            }
+            let (cells, joins) = self.flow_path(grid, &filled, &owner, source);
+            if cells.len() < self.min_length.max(2) {
+                continue;
+            }
+
+            let river = rivers.len();
+            let mut surface = f32::INFINITY;
+            let mut points: Vec<RiverPoint> = cells
+                .iter()
+                .enumerate()
+                .map(|(i, cell)| {
+                    surface = surface.min(grid.data[*cell]);
+                    let (x, y) = (cell % grid.width, cell / grid.width);
+                    RiverPoint {
+                        position: Vec3::new(x as f32, surface, y as f32),
+                        discharge: (i + 1) as f32,
+                        width: 0.,
+                        depth: 0.,
+                    }
+                })
+                .collect();
+            for (i, cell) in cells.iter().enumerate() {
+                owner[*cell] = Some((river, i));
+            }
+
+            let tributary_of = joins.map(|(into, at)| {
+                // meet the water where it is, the confluence belongs to the river joined
+                let confluence = rivers[into].points[at];
+                let drained = cells.len() as f32;
+                let mut last = confluence;
+                last.position.y = last.position.y.min(surface);
+                last.discharge += drained;
+                points.push(last);
+                add_discharge(&mut rivers, into, at, drained);
+                into
+            });
+            rivers.push(River {
+                points,
+                tributary_of,
+            });
+        }
+
+        let max_discharge = rivers
+            .iter()
+            .flat_map(|river| river.points.iter())
+            .fold(1f32, |max, point| max.max(point.discharge));
+        for point in rivers.iter_mut().flat_map(|river| river.points.iter_mut()) {
+            let t = (point.discharge / max_discharge).sqrt();
+            point.width = self.width.at(t);
+            point.depth = self.depth.at(t);
+        }
+        rivers
+    }
+
+    /// Random interior cells above `min_source_height`, highest first
+    fn pick_sources(&self, grid: &HeightGrid) -> Vec<usize> {
+        let (min, max) = grid
+            .data
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), h| {
+                (min.min(*h), max.max(*h))
+            });
+        let threshold = min + (max - min) * self.min_source_height;
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut sources = vec![];
+        for _ in 0..self.sources * 32 {
+            if sources.len() == self.sources {
+                break;
+            }
+            let x = rng.gen_range(1..grid.width - 1);
+            let y = rng.gen_range(1..grid.depth - 1);
+            let cell = grid.index(x, y);
+            if grid.data[cell] >= threshold && !sources.contains(&cell) {
+                sources.push(cell);
+            }
+        }
+        sources.sort_by(|a, b| grid.data[*b].total_cmp(&grid.data[*a]));
+        sources
+    }
+
+    /// Cells from `source` down the steepest filled slope, and the river and point it runs
+    /// into if it meets one
+    fn flow_path(
+        &self,
+        grid: &HeightGrid,
+        filled: &[f32],
+        owner: &[Option<(usize, usize)>],
+        source: usize,
+    ) -> (Vec<usize>, Option<(usize, usize)>) {
+        let mut cells = vec![source];
+        let mut cell = source;
+        loop {
+            let (x, y) = (cell % grid.width, cell / grid.width);
+            let on_edge = x == 0 || y == 0 || x == grid.width - 1 || y == grid.depth - 1;
+            let in_sea = self.sea_level.is_some_and(|sea| grid.data[cell] < sea);
+            if on_edge || in_sea {
+                return (cells, None);
+            }
+
+            let mut next = None;
+            let mut steepest = 0.;
+            for (dx, dy) in NEIGHBOURS {
+                let (nx, ny) = (x.wrapping_add_signed(dx), y.wrapping_add_signed(dy));
+                let neighbour = grid.index(nx, ny);
+                let distance = if dx != 0 && dy != 0 { SQRT_2 } else { 1. };
+                let slope = (filled[cell] - filled[neighbour]) / distance;
+                if slope > steepest {
+                    steepest = slope;
+                    next = Some(neighbour);
+                }
+            }
+            // filled heights always have a way down, this only guards against NaN heights
+            let Some(next) = next else {
+                return (cells, None);
+            };
+            if let Some(joined) = owner[next] {
+                return (cells, Some(joined));
+            }
+            cells.push(next);
+            cell = next;
+        }
+    }
+}
+
+impl RiverProfile {
+    pub fn at(&self, t: f32) -> f32 {
+        self.source + (self.mouth - self.source) * t
+    }
+}
+
+impl River {
+    /// Curve through every point's position, one segment per pair of neighbouring points.
+    /// Traced rivers always have at least 2 points.
+    pub fn spline(&self) -> CubicCurve<Vec3> {
+        let positions: Vec<Vec3> = self.points.iter().map(|p| p.position).collect();
+        CubicCardinalSpline::new_catmull_rom(positions).to_curve()
+    }
+
+    /// The river over a terrain built from the grid with `height_multiplier`, whose grid
+    /// origin sits at `origin`. Grid cells are one world unit apart, so only heights and depths
+    /// are scaled.
+    pub fn to_world(&self, height_multiplier: f32, origin: Vec3) -> River {
+        let scale = Vec3::new(1., height_multiplier, 1.);
+        let points = self
+            .points
+            .iter()
+            .map(|point| RiverPoint {
+                position: origin + point.position * scale,
+                depth: point.depth * height_multiplier,
+                ..*point
+            })
+            .collect();
+        River {
+            points,
+            tributary_of: self.tributary_of,
+        }
+    }
+}
+
+/// Adds `discharge` to the river from point `at` down, and on into every river it flows into
+fn add_discharge(rivers: &mut [River], mut river: usize, mut at: usize, discharge: f32) {
+    loop {
+        for point in rivers[river].points[at..].iter_mut() {
+            point.discharge += discharge;
+        }
+        let Some(into) = rivers[river].tributary_of else {
+            return;
+        };
+        // the last point is the confluence, which is a point of the river joined
+        let confluence = rivers[river].points.last().unwrap().position;
+        at = rivers[into]
+            .points
+            .iter()
+            .position(|p| p.position.x == confluence.x && p.position.z == confluence.z)
+            .unwrap();
+        river = into;
+    }
+}
+
+/// Lowers the grid along every river to a bowl `width` wide and `depth` deep under the water
+/// surface. Heights are only ever lowered, so overlapping channels merge.
+pub fn carve(grid: &mut HeightGrid, rivers: &[River]) {
+    for river in rivers {
+        for pair in river.points.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let (start, end) = (a.position.xz(), b.position.xz());
+            let reach = a.width.max(b.width) / 2. + 1.;
+            let min = (start.min(end) - reach).max(Vec2::ZERO).floor();
+            let max = (start.max(end) + reach)
+                .min(Vec2::new((grid.width - 1) as f32, (grid.depth - 1) as f32))
+                .ceil();
+
+            for y in min.y as usize..=max.y as usize {
+                for x in min.x as usize..=max.x as usize {
+                    let p = Vec2::new(x as f32, y as f32);
+                    let along = end - start;
+                    let t = ((p - start).dot(along) / along.length_squared()).clamp(0., 1.);
+                    let half_width = (a.width + (b.width - a.width) * t) / 2.;
+                    if half_width <= 0. || half_width.is_nan() {
+                        continue;
+                    }
+                    let s = p.distance(start + along * t) / half_width;
+                    if s >= 1. {
+                        continue;
+                    }
+                    let surface = a.position.y + (b.position.y - a.position.y) * t;
+                    let depth = a.depth + (b.depth - a.depth) * t;
+                    let bed = surface - depth * (1. - s * s);
+                    if bed < grid.get(x, y) {
+                        grid.set(x, y, bed);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Heights raised just enough for every cell to have a strictly lower neighbour on the way to
+/// the grid's edge, filling pits from the edge inwards lowest first
+fn fill_pits(grid: &HeightGrid) -> Vec<f32> {
+    let mut filled = grid.data.clone();
+    let mut visited = vec![false; filled.len()];
+    let mut open = BinaryHeap::new();
+    for y in 0..grid.depth {
+        for x in 0..grid.width {
+            if x == 0 || y == 0 || x == grid.width - 1 || y == grid.depth - 1 {
+                let cell = grid.index(x, y);
+                visited[cell] = true;
+                open.push(Lowest(filled[cell], cell));
+            }
+        }
+    }
+
+    while let Some(Lowest(height, cell)) = open.pop() {
+        let (x, y) = (cell % grid.width, cell / grid.width);
+        for (dx, dy) in NEIGHBOURS {
+            let (nx, ny) = (x.wrapping_add_signed(dx), y.wrapping_add_signed(dy));
+            if nx >= grid.width || ny >= grid.depth {
+                continue;
+            }
+            let neighbour = grid.index(nx, ny);
+            if visited[neighbour] {
+                continue;
+            }
+            visited[neighbour] = true;
+            filled[neighbour] = filled[neighbour].max(height + FILL_STEP);
+            open.push(Lowest(filled[neighbour], neighbour));
+        }
+    }
+    filled
+}
+
+/// Orders a max heap lowest height first
+#[derive(Debug, PartialEq)]
+struct Lowest(f32, usize);
+
+impl Eq for Lowest {}
+
+impl PartialOrd for Lowest {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Lowest {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0).then(other.1.cmp(&self.1))
+    }
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::{carve, RiverNetwork, RiverProfile};
+    use crate::world::{
+        erosion::{ErodedSampler, HeightFilter},
+        heightmap::HeightGrid,
+        noise::NoiseSampler,
+        rtin::PlaneSampler,
+    };
+    use bevy::math::{Vec3, Vec3Swizzles};
+    use noise::{Fbm, Perlin};
+
+    fn sampler() -> NoiseSampler {
+        let mut noise = Fbm::<Perlin>::new(3);
+        noise.frequency = 0.03;
+        NoiseSampler::single_layer(noise)
+    }
+
+    fn grid(width: usize, depth: usize) -> HeightGrid {
+        ErodedSampler::new(
+            sampler(),
+            width - 1,
+            depth - 1,
+            &Vec::<Box<dyn HeightFilter>>::new(),
+        )
+        .grid()
+        .clone()
+    }
+
+    fn network(seed: u64) -> RiverNetwork {
+        RiverNetwork {
+            seed,
+            min_length: 8,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rivers_run_downhill() {
+        let grid = grid(96, 64);
+        let rivers = network(1).trace(&grid);
+        assert!(!rivers.is_empty());
+        for (i, river) in rivers.iter().enumerate() {
+            for pair in river.points.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                assert!(b.position.y <= a.position.y);
+                assert!(b.discharge > a.discharge);
+                assert!(b.width >= a.width && b.depth >= a.depth);
+                // neighbouring cells
+                assert!((b.position - a.position).xz().abs().max_element() == 1.);
+            }
+            match river.tributary_of {
+                Some(into) => assert!(into < i),
+                None => {
+                    let end = river.points.last().unwrap().position;
+                    assert!(end.x == 0. || end.z == 0. || end.x == 95. || end.z == 63.);
+                }
+            }
+        }
+        assert_eq!(rivers, network(1).trace(&grid));
+        assert_ne!(rivers, network(2).trace(&grid));
+    }
+
+    #[test]
+    fn tributaries_merge() {
+        // two slopes falling towards a valley along x = 32, which gently falls towards y = 0
+        let (width, depth) = (65, 48);
+        let data = (0..width * depth)
+            .map(|i| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                (x - 32.).abs() * 0.02 + y * 0.005
+            })
+            .collect();
+        let grid = HeightGrid::new(width, depth, data);
+        let rivers = RiverNetwork {
+            sources: 8,
+            min_length: 4,
+            ..Default::default()
+        }
+        .trace(&grid);
+
+        let main: Vec<_> = rivers.iter().filter(|r| r.tributary_of.is_none()).collect();
+        assert_eq!(main.len(), 1);
+        assert!(rivers.len() > 1);
+        // everything drains through the valley
+        let mouth = main[0].points.last().unwrap();
+        assert_eq!((mouth.position.x, mouth.position.z), (32., 0.));
+        let cells: usize = rivers
+            .iter()
+            .map(|r| r.points.len() - r.tributary_of.is_some() as usize)
+            .sum();
+        assert_eq!(mouth.discharge, cells as f32);
+        assert_eq!(mouth.width, RiverNetwork::default().width.mouth);
+    }
+
+    #[test]
+    fn carves_channels() {
+        let original = grid(96, 64);
+        let mut carved = original.clone();
+        let rivers = network(1).generate(&mut carved);
+        assert!(carved
+            .data
+            .iter()
+            .zip(original.data.iter())
+            .all(|(c, o)| c <= o));
+        for point in rivers.iter().flat_map(|r| r.points.iter()) {
+            let (x, y) = (point.position.x as usize, point.position.z as usize);
+            assert!(carved.get(x, y) <= point.position.y - point.depth + 1e-6);
+        }
+
+        // running the filter carves the same channels
+        let mut filtered = original.clone();
+        network(1).apply(&mut filtered);
+        assert_eq!(filtered, carved);
+
+        // rivers without any width are traced but leave the grid alone
+        let mut untouched = original.clone();
+        let dry = RiverNetwork {
+            width: RiverProfile {
+                source: 0.,
+                mouth: 0.,
+            },
+            ..network(1)
+        };
+        assert!(!dry.generate(&mut untouched).is_empty());
+        assert_eq!(untouched, original);
+    }
+
+    #[test]
+    fn spline_follows_river() {
+        let rivers = network(1).trace(&grid(96, 64));
+        for river in rivers.iter() {
+            let curve = river.spline();
+            for position in curve.iter_positions(4) {
+                let closest = river
+                    .points
+                    .iter()
+                    .map(|p| p.position.distance(position))
+                    .fold(f32::INFINITY, f32::min);
+                assert!(closest < 2., "spline strays {closest} from the river");
+            }
+        }
+    }
+
+    #[test]
+    fn rivers_placed_in_world() {
+        let grid = grid(96, 64);
+        let origin = Vec3::new(-48., 2., -32.);
+        for river in network(1).trace(&grid) {
+            let world = river.to_world(10., origin);
+            assert_eq!(world.tributary_of, river.tributary_of);
+            for (point, world) in river.points.iter().zip(world.points.iter()) {
+                assert_eq!(world.position.xz(), origin.xz() + point.position.xz());
+                assert_eq!(world.position.y, origin.y + point.position.y * 10.);
+                assert_eq!(world.width, point.width);
+                assert_eq!(world.depth, point.depth * 10.);
+            }
+            let end = world.points.last().unwrap().position;
+            assert!(
+                world
+                    .spline()
+                    .position(world.points.len() as f32 - 1.)
+                    .distance(end)
+                    < 1e-3
+            );
+        }
+    }
+
+    #[test]
+    fn rivers_have_splines_without_min_length() {
+        let grid = grid(96, 64);
+        for min_length in [0, 1] {
+            let rivers = RiverNetwork {
+                min_length,
+                sources: 64,
+                ..network(1)
+            }
+            .trace(&grid);
+            assert!(!rivers.is_empty());
+            for river in rivers.iter() {
+                assert!(river.points.len() >= 2);
+                assert_eq!(river.spline().segments().len(), river.points.len() - 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use super::{HeightFilter, NEIGHBOURS};
+use crate::world::heightmap::HeightGrid;
+use std::f32::consts::SQRT_2;
+
+/// Thermal erosion, material slides from every cell to its lower neighbours for as long as the
+/// slope between them is steeper than `talus_angle`. Sliding keeps the total amount of material
+/// the same, but large slopes take many iterations to settle, which `clamp` makes up for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThermalErosion {
+    pub iterations: usize,
+    /// Steepest slope left standing between two neighbouring cells, in radians. A triangle
+    /// spanning both axes can be up to √2 times steeper than that, so the default of 35° keeps
+    /// every mesh face under 45°, where the player controller still gets traction.
+    pub talus_angle: f32,
+    /// World units per unit of grid height, usually the terrain's height multiplier. Grid cells
+    /// are one world unit apart.
+    pub height_scale: f32,
+    /// Fraction of the excess moved per iteration, in (0, 1]
+    pub rate: f32,
+    /// Finish by cutting down whatever is still steeper than `talus_angle`, so no slope in the
+    /// grid is left above it
+    pub clamp: bool,
+}
+
+impl Default for ThermalErosion {
+    fn default() -> Self {
+        Self {
+            iterations: 100,
+            talus_angle: 35f32.to_radians(),
+            height_scale: 1.,
+            rate: 0.5,
+            clamp: true,
+        }
+    }
+}
+
+impl HeightFilter for ThermalErosion {
+    fn apply(&self, grid: &mut HeightGrid) {
+        let max_rise = self.talus_angle.tan() / self.height_scale;
+        let mut delta = vec![0.; grid.data.len()];
+
+        for _ in 0..self.iterations {
+            delta.fill(0.);
+            // every cell works from the heights at the start of the iteration, so the result
+            // doesn't depend on the order cells are visited in
+            for y in 0..grid.depth {
+                for x in 0..grid.width {
+                    let height = grid.get(x, y);
+                    let mut excess = [(0, 0.); 8];
+                    let mut total = 0.;
+                    let mut max = 0f32;
+
+                    for (i, (dx, dy)) in NEIGHBOURS.iter().enumerate() {
+                        let (Some(nx), Some(ny)) =
+                            (x.checked_add_signed(*dx), y.checked_add_signed(*dy))
+                        else {
+                            continue;
+                        };
+                        if nx >= grid.width || ny >= grid.depth {
+                            continue;
+                        }
+                        let distance = if *dx != 0 && *dy != 0 { SQRT_2 } else { 1. };
+                        let over = height - grid.get(nx, ny) - max_rise * distance;
+                        if over > 0. {
+                            excess[i] = (grid.index(nx, ny), over);
+                            total += over;
+                            max = max.max(over);
+                        }
+                    }
+                    if total <= 0. {
+                        continue;
+                    }
+
+                    // moving half of the largest excess levels that neighbour exactly
+                    let amount = self.rate * max / 2.;
+                    delta[grid.index(x, y)] -= amount;
+                    for (idx, over) in excess.iter().filter(|(_, over)| *over > 0.) {
+                        delta[*idx] += amount * over / total;
+                    }
+                }
+            }
+
+            if delta.iter().all(|d| *d == 0.) {
+                break;
+            }
+            for (height, d) in grid.data.iter_mut().zip(&delta) {
+                *height += d;
+            }
+        }
+
+        if self.clamp {
+            clamp_slopes(grid, max_rise);
+        }
+    }
+}
+
+/// Lowers every cell to at most `max_rise` per unit of distance above each of its neighbours.
+/// A forward and a backward sweep are enough, like a chamfer distance transform.
+fn clamp_slopes(grid: &mut HeightGrid, max_rise: f32) {
+    let (width, depth) = (grid.width, grid.depth);
+    let mut relax = |x: usize, y: usize, neighbours: &[(isize, isize)]| {
+        for (dx, dy) in neighbours {
+            let (Some(nx), Some(ny)) = (x.checked_add_signed(*dx), y.checked_add_signed(*dy))
+            else {
+                continue;
+            };
+            if nx >= width || ny >= depth {
+                continue;
+            }
+            let distance = if *dx != 0 && *dy != 0 { SQRT_2 } else { 1. };
+            let limit = grid.get(nx, ny) + max_rise * distance;
+            if grid.get(x, y) > limit {
+                grid.set(x, y, limit);
+            }
+        }
+    };
+
+    for y in 0..depth {
+        for x in 0..width {
+            relax(x, y, &NEIGHBOURS[..4]);
+        }
+    }
+    for y in (0..depth).rev() {
+        for x in (0..width).rev() {
+            relax(x, y, &NEIGHBOURS[4..]);
+        }
+    }
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::ThermalErosion;
+    use crate::world::{
+        erosion::{ErodedSampler, HeightFilter, HydraulicErosion, NEIGHBOURS},
+        heightmap::HeightGrid,
+        noise::NoiseSampler,
+        rtin::build_terrain_from_sampler,
+    };
+    use noise::{Fbm, Perlin};
+    use std::f32::consts::SQRT_2;
+
+    fn steepest(grid: &HeightGrid) -> f32 {
+        let mut steepest = 0f32;
+        for y in 1..grid.depth - 1 {
+            for x in 1..grid.width - 1 {
+                for (dx, dy) in NEIGHBOURS {
+                    let (nx, ny) = (x.wrapping_add_signed(dx), y.wrapping_add_signed(dy));
+                    let distance = if dx != 0 && dy != 0 { SQRT_2 } else { 1. };
+                    steepest = steepest.max((grid.get(x, y) - grid.get(nx, ny)) / distance);
+                }
+            }
+        }
+        steepest
+    }
+
+    #[test]
+    fn spikes_flattened_to_talus() {
+        let mut grid = HeightGrid::new(16, 16, vec![0.; 256]);
+        grid.set(8, 8, 10.);
+        grid.set(12, 3, 6.);
+        // without the clamp, so everything has to settle by sliding
+        let thermal = ThermalErosion {
+            clamp: false,
+            ..Default::default()
+        };
+        thermal.apply(&mut grid);
+
+        assert!(steepest(&grid) <= thermal.talus_angle.tan() + 1e-3);
+        let total: f32 = grid.data.iter().sum();
+        assert!((total - 16.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn talus_in_world_units() {
+        let mut grid = HeightGrid::new(8, 8, vec![0.; 64]);
+        grid.set(4, 4, 1.);
+        let thermal = ThermalErosion {
+            talus_angle: 45f32.to_radians(),
+            height_scale: 10.,
+            ..Default::default()
+        };
+        thermal.apply(&mut grid);
+        // a rise of 1 world unit per cell is 0.1 in grid heights
+        assert!(steepest(&grid) <= 0.1 + 1e-4);
+    }
+
+    #[test]
+    fn chained_after_hydraulic() {
+        let mut noise = Fbm::<Perlin>::new(3);
+        noise.frequency = 0.05;
+        let thermal = ThermalErosion {
+            height_scale: 50.,
+            ..Default::default()
+        };
+        let hydraulic = HydraulicErosion {
+            iterations: 1_000,
+            ..Default::default()
+        };
+        let sampler = ErodedSampler::new(
+            NoiseSampler::single_layer(noise),
+            32,
+            32,
+            &(hydraulic, thermal.clone()),
+        );
+        let max_rise = thermal.talus_angle.tan() / thermal.height_scale;
+        assert!(steepest(sampler.grid()) <= max_rise + 1e-4);
+    }
+
+    #[test]
+    fn default_talus_keeps_faces_walkable() {
+        // steep enough that the clamp has to cut most of it down
+        let mut noise = Fbm::<Perlin>::new(9);
+        noise.frequency = 0.1;
+        let thermal = ThermalErosion {
+            iterations: 0,
+            height_scale: 40.,
+            ..Default::default()
+        };
+        let sampler = ErodedSampler::new(NoiseSampler::single_layer(noise), 32, 32, &thermal);
+        for threshold in [0., 0.05] {
+            let terrain = build_terrain_from_sampler(&sampler, 40., 32., threshold);
+            for triangle in 0..terrain.indices.len() / 3 {
+                let normal = terrain.face_normal(triangle);
+                assert!(normal.y.abs() > 0.7, "face {triangle} too steep: {normal}");
+            }
+        }
+    }
+}
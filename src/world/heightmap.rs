@@ -0,0 +1,356 @@
+use super::rtin::PlaneSampler;
+use bevy::math::Vec2;
+use std::{fmt::Display, path::Path};
+
+/// Row major grid of heights, `width` samples along x and `depth` samples along y
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeightGrid {
+    pub width: usize,
+    pub depth: usize,
+    pub data: Vec<f32>,
+}
+
+impl HeightGrid {
+    pub fn new(width: usize, depth: usize, data: Vec<f32>) -> Self {
+        assert_eq!(width * depth, data.len(), "height grid data has the wrong length");
+        Self { width, depth, data }
+    }
+
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.data[self.index(x, y)]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, height: f32) {
+        let idx = self.index(x, y);
+        self.data[idx] = height;
+    }
+
+    /// Bilinearly interpolated height at fractional grid coordinates, clamped to the grid edges
+    pub fn bilinear(&self, x: f32, y: f32) -> f32 {
+        let x = x.clamp(0., (self.width - 1) as f32);
+        let y = y.clamp(0., (self.depth - 1) as f32);
+        let x0 = x.floor() as usize;
+        let y0 = y.floor() as usize;
+        let x1 = (x0 + 1).min(self.width - 1);
+        let y1 = (y0 + 1).min(self.depth - 1);
+        let tx = x - x0 as f32;
+        let ty = y - y0 as f32;
+
+        let top = self.get(x0, y0) * (1. - tx) + self.get(x1, y0) * tx;
+        let bottom = self.get(x0, y1) * (1. - tx) + self.get(x1, y1) * tx;
+        top * (1. - ty) + bottom * ty
+    }
+}
+
+#[derive(Debug)]
+pub enum HeightmapError {
+    Io(std::io::Error),
+    Png(png::DecodingError),
+    UnsupportedPng(png::ColorType, png::BitDepth),
+    UnsupportedExtension(String),
+    /// Raw files carry no header, so their length has to match the expected sample count
+    SizeMismatch { expected: usize, actual: usize },
+    /// Sampling interpolates between neighbours, so each axis needs at least 2 samples
+    TooSmall { width: usize, depth: usize },
+}
+
+impl Display for HeightmapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "could not read heightmap: {err}"),
+            Self::Png(err) => write!(f, "could not decode heightmap png: {err}"),
+            Self::UnsupportedPng(color, depth) => {
+                write!(f, "png heightmap must be grayscale, got {color:?} at {depth:?}")
+            }
+            Self::UnsupportedExtension(ext) => write!(f, "unsupported heightmap extension: {ext}"),
+            Self::SizeMismatch { expected, actual } => {
+                write!(f, "expected {expected} bytes of raw heights, got {actual}")
+            }
+            Self::TooSmall { width, depth } => {
+                write!(f, "heightmap must be at least 2 x 2, got {width} x {depth}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HeightmapError {}
+
+impl From<std::io::Error> for HeightmapError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<png::DecodingError> for HeightmapError {
+    fn from(value: png::DecodingError) -> Self {
+        Self::Png(value)
+    }
+}
+
+/// Samples an authored heightmap. Integer formats are normalized to [0, 1] before
+/// `vertical_scale` is applied, r32 files are read as is.
+#[derive(Debug, Clone)]
+pub struct HeightmapSampler {
+    grid: HeightGrid,
+    /// World units between two neighbouring pixels
+    pub horizontal_scale: f32,
+    pub vertical_scale: f32,
+}
+
+impl PlaneSampler for HeightmapSampler {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        self.grid
+            .bilinear(x / self.horizontal_scale, y / self.horizontal_scale)
+            * self.vertical_scale
+    }
+}
+
+impl HeightmapSampler {
+    pub fn new(grid: HeightGrid) -> Self {
+        Self {
+            grid,
+            horizontal_scale: 1.,
+            vertical_scale: 1.,
+        }
+    }
+
+    pub fn with_scale(mut self, horizontal_scale: f32, vertical_scale: f32) -> Self {
+        self.horizontal_scale = horizontal_scale;
+        self.vertical_scale = vertical_scale;
+        self
+    }
+
+    pub fn grid(&self) -> &HeightGrid {
+        &self.grid
+    }
+
+    /// Size of the heightmap in world units
+    pub fn world_size(&self) -> Vec2 {
+        Vec2::new(
+            (self.grid.width - 1) as f32,
+            (self.grid.depth - 1) as f32,
+        ) * self.horizontal_scale
+    }
+
+    /// Loads a file by extension. Raw `.r16` and `.r32` files are assumed to be square.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HeightmapError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+
+        match ext.as_str() {
+            "png" => Self::from_png_bytes(&bytes),
+            "r16" | "raw" => {
+                let side = square_side(bytes.len() / 2);
+                Self::from_r16_bytes(&bytes, side, side)
+            }
+            "r32" => {
+                let side = square_side(bytes.len() / 4);
+                Self::from_r32_bytes(&bytes, side, side)
+            }
+            other => Err(HeightmapError::UnsupportedExtension(other.to_string())),
+        }
+    }
+
+    /// Accepts 8 and 16 bit grayscale pngs, an alpha channel is ignored
+    pub fn from_png_bytes(bytes: &[u8]) -> Result<Self, HeightmapError> {
+        let mut decoder = png::Decoder::new(bytes);
+        // expands sub 8 bit grayscale, but leaves 16 bit samples alone
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        let buf = &buf[..info.buffer_size()];
+
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            other => return Err(HeightmapError::UnsupportedPng(other, info.bit_depth)),
+        };
+
+        let data = match info.bit_depth {
+            png::BitDepth::Eight => buf
+                .chunks_exact(channels)
+                .map(|px| px[0] as f32 / u8::MAX as f32)
+                .collect(),
+            // png stores 16 bit samples big endian
+            png::BitDepth::Sixteen => buf
+                .chunks_exact(channels * 2)
+                .map(|px| u16::from_be_bytes([px[0], px[1]]) as f32 / u16::MAX as f32)
+                .collect(),
+            other => return Err(HeightmapError::UnsupportedPng(info.color_type, other)),
+        };
+
+        let (width, depth) = (info.width as usize, info.height as usize);
+        check_size(width, depth)?;
+        Ok(Self::new(HeightGrid::new(width, depth, data)))
+    }
+
+    /// Raw little endian unsigned 16 bit heights
+    pub fn from_r16_bytes(
+        bytes: &[u8],
+        width: usize,
+        depth: usize,
+    ) -> Result<Self, HeightmapError> {
+        check_size(width, depth)?;
+        check_raw_len(bytes, width * depth * 2)?;
+        let data = bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as f32 / u16::MAX as f32)
+            .collect();
+        Ok(Self::new(HeightGrid::new(width, depth, data)))
+    }
+
+    /// Raw little endian 32 bit float heights
+    pub fn from_r32_bytes(
+        bytes: &[u8],
+        width: usize,
+        depth: usize,
+    ) -> Result<Self, HeightmapError> {
+        check_size(width, depth)?;
+        check_raw_len(bytes, width * depth * 4)?;
+        let data = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        Ok(Self::new(HeightGrid::new(width, depth, data)))
+    }
+}
+
+fn square_side(samples: usize) -> usize {
+    (samples as f64).sqrt().round() as usize
+}
+
+fn check_size(width: usize, depth: usize) -> Result<(), HeightmapError> {
+    if width < 2 || depth < 2 {
+        return Err(HeightmapError::TooSmall { width, depth });
+    }
+    Ok(())
+}
+
+fn check_raw_len(bytes: &[u8], expected: usize) -> Result<(), HeightmapError> {
+    if bytes.len() != expected {
+        return Err(HeightmapError::SizeMismatch {
+            expected,
+            actual: bytes.len(),
+        });
+    }
+    Ok(())
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::{HeightGrid, HeightmapError, HeightmapSampler};
+    use crate::world::rtin::{build_terrain_from_sampler, PlaneSampler};
+
+    fn encode_png(width: u32, height: u32, depth: png::BitDepth, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut out, width, height);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_depth(depth);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(data).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn bilinear_interpolates() {
+        let grid = HeightGrid::new(2, 2, vec![0., 1., 2., 3.]);
+        assert_eq!(grid.bilinear(0., 0.), 0.);
+        assert_eq!(grid.bilinear(1., 1.), 3.);
+        assert_eq!(grid.bilinear(0.5, 0.), 0.5);
+        assert_eq!(grid.bilinear(0.5, 0.5), 1.5);
+        // clamped outside of the grid
+        assert_eq!(grid.bilinear(5., -2.), 1.);
+    }
+
+    #[test]
+    fn png_8_bit_loads() {
+        let bytes = encode_png(2, 2, png::BitDepth::Eight, &[0, 255, 51, 102]);
+        let sampler = HeightmapSampler::from_png_bytes(&bytes).unwrap();
+        assert_eq!(sampler.grid().width, 2);
+        assert_eq!(sampler.get(1., 0.), 1.);
+        assert!((sampler.get(0., 1.) - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn png_16_bit_loads() {
+        let data = [0xff, 0xff, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let bytes = encode_png(2, 2, png::BitDepth::Sixteen, &data);
+        let sampler = HeightmapSampler::from_png_bytes(&bytes).unwrap();
+        assert_eq!(sampler.get(0., 0.), 1.);
+        assert!((sampler.get(1., 0.) - 0x8000 as f32 / u16::MAX as f32).abs() < 1e-6);
+    }
+
+    #[test]
+    fn raw_formats_load() {
+        let r16: Vec<u8> = [0u16, u16::MAX, 0, u16::MAX]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let sampler = HeightmapSampler::from_r16_bytes(&r16, 2, 2)
+            .unwrap()
+            .with_scale(10., 5.);
+        assert_eq!(sampler.get(10., 0.), 5.);
+        assert_eq!(sampler.get(5., 5.), 2.5);
+
+        let r32: Vec<u8> = [1.5f32, -2., 3., 4.]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let sampler = HeightmapSampler::from_r32_bytes(&r32, 2, 2).unwrap();
+        assert_eq!(sampler.get(0., 1.), 3.);
+
+        assert!(matches!(
+            HeightmapSampler::from_r32_bytes(&r32, 3, 3),
+            Err(HeightmapError::SizeMismatch {
+                expected: 36,
+                actual: 16
+            })
+        ));
+    }
+
+    #[test]
+    fn tiny_grids_rejected() {
+        assert!(matches!(
+            HeightmapSampler::from_r16_bytes(&[], 0, 0),
+            Err(HeightmapError::TooSmall { width: 0, depth: 0 })
+        ));
+        assert!(matches!(
+            HeightmapSampler::from_r32_bytes(&1f32.to_le_bytes(), 1, 1),
+            Err(HeightmapError::TooSmall { width: 1, depth: 1 })
+        ));
+        let png = encode_png(3, 1, png::BitDepth::Eight, &[0, 128, 255]);
+        assert!(matches!(
+            HeightmapSampler::from_png_bytes(&png),
+            Err(HeightmapError::TooSmall { width: 3, depth: 1 })
+        ));
+
+        let path = std::env::temp_dir().join("empty_heightmap_test.r16");
+        std::fs::write(&path, []).unwrap();
+        let loaded = HeightmapSampler::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(loaded, Err(HeightmapError::TooSmall { .. })));
+    }
+
+    #[test]
+    fn builds_terrain() {
+        let size = 8;
+        let data = (0..(size + 1) * (size + 1)).map(|i| (i % 7) as f32).collect();
+        let sampler = HeightmapSampler::new(HeightGrid::new(size + 1, size + 1, data));
+        let terrain = build_terrain_from_sampler(&sampler, 1., size as f32, 0.);
+        for vertex in terrain.vertices {
+            assert_eq!(vertex.y, sampler.get(vertex.x, vertex.z));
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use super::{
+    biome::{apply_biomes, BiomeSource},
+    query::TerrainSurface,
+    rtin::{
+        build_chunk_terrain_from_sampler, build_terrain_rect_from_sampler,
+        coloring::TerrainColoring, stats::TerrainStats, InvalidExtent, PlaneSampler,
+    },
+    terrain::{is_power_of_2, TerrainBundle, TerrainColliderMode},
+};
+use bevy::{
+    prelude::*,
+    tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task},
+    utils::HashMap,
+};
+use bevy_rapier3d::prelude::Collider;
+use std::{fmt::Display, sync::Arc};
+
+pub struct TerrainJobsPlugin;
+
+impl Plugin for TerrainJobsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TerrainBuildFinished>()
+            .init_resource::<TerrainBuildStats>()
+            .register_type::<TerrainBuildStats>()
+            .add_systems(Update, poll_terrain_builds);
+    }
+}
+
+/// Everything needed to build a terrain mesh and collider away from the main thread
+#[derive(Clone)]
+pub struct TerrainBuildRequest {
+    pub sampler: Arc<dyn PlaneSampler>,
+    pub height_multiplier: f32,
+    /// Extent along x, any whole number of grid cells
+    pub width: f32,
+    /// Extent along z, any whole number of grid cells
+    pub depth: f32,
+    pub error_threshold: f32,
+    /// Keep every border vertex, see `build_chunk_terrain_from_sampler`. Chunks are square,
+    /// so this needs `width == depth`, checked by `validate`, and a power of 2.
+    pub lock_edges: bool,
+    pub collider_mode: TerrainColliderMode,
+    /// Vertex colors and splat weights, the mesh is left uncolored without one
+    pub coloring: Option<TerrainColoring>,
+    /// Depth of the skirts hung under the mesh borders, see `TerrainMeshData::add_skirts`
+    pub skirt_depth: Option<f32>,
+    /// Tags every vertex with its biome, read at the same coordinates as `sampler`
+    pub biomes: Option<Arc<dyn BiomeSource>>,
+    pub transform: Transform,
+    pub name: Name,
+}
+
+pub struct TerrainBuildOutput {
+    pub mesh: Mesh,
+    pub collider: Collider,
+    pub surface: TerrainSurface,
+    pub stats: Option<TerrainStats>,
+    pub transform: Transform,
+    pub name: Name,
+}
+
+/// Why a request can't be built, checked before any sampling
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TerrainBuildError {
+    Extent(InvalidExtent),
+    /// `lock_edges` builds a square chunk
+    ChunkNotSquare {
+        width: f32,
+        depth: f32,
+    },
+    /// `lock_edges` splits the chunk in half down to single cells
+    ChunkNotPowerOfTwo(f32),
+}
+
+impl Display for TerrainBuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Extent(err) => err.fmt(f),
+            Self::ChunkNotSquare { width, depth } => {
+                write!(f, "terrain chunks must be square, got {width} x {depth}")
+            }
+            Self::ChunkNotPowerOfTwo(size) => {
+                write!(f, "terrain chunk size must be a power of 2, got {size}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TerrainBuildError {}
+
+impl From<InvalidExtent> for TerrainBuildError {
+    fn from(value: InvalidExtent) -> Self {
+        Self::Extent(value)
+    }
+}
+
+/// Stats of the latest finished build of every terrain, by the build's name
+#[derive(Resource, Reflect, Debug, Default)]
+#[reflect(Resource)]
+pub struct TerrainBuildStats(pub HashMap<String, TerrainStats>);
+
+impl TerrainBuildRequest {
+    pub fn validate(&self) -> Result<(), TerrainBuildError> {
+        InvalidExtent::check(self.width, self.depth)?;
+        if self.lock_edges && self.width != self.depth {
+            return Err(TerrainBuildError::ChunkNotSquare {
+                width: self.width,
+                depth: self.depth,
+            });
+        }
+        if self.lock_edges && !is_power_of_2(self.width) {
+            return Err(TerrainBuildError::ChunkNotPowerOfTwo(self.width));
+        }
+        Ok(())
+    }
+
+    pub fn build(self) -> Result<TerrainBuildOutput, TerrainBuildError> {
+        self.validate()?;
+        let mut terrain = if self.lock_edges {
+            build_chunk_terrain_from_sampler(
+                &self.sampler,
+                self.height_multiplier,
+                self.width,
+                self.error_threshold,
+            )
+        } else {
+            build_terrain_rect_from_sampler(
+                &self.sampler,
+                self.height_multiplier,
+                self.width,
+                self.depth,
+                self.error_threshold,
+            )?
+        };
+        if let Some(depth) = self.skirt_depth {
+            terrain.add_skirts(depth);
+        }
+        let mut mesh = terrain.into_mesh(false, self.width.max(self.depth));
+        if let Some(coloring) = &self.coloring {
+            coloring.apply(&mut mesh);
+        }
+        if let Some(biomes) = &self.biomes {
+            apply_biomes(&mut mesh, biomes.as_ref());
+        }
+        let collider = self.collider_mode.collider(
+            &mesh,
+            &self.sampler,
+            self.height_multiplier,
+            self.width,
+            self.depth,
+        );
+
+        Ok(TerrainBuildOutput {
+            mesh,
+            collider,
+            surface: TerrainSurface {
+                sampler: self.sampler,
+                height_multiplier: self.height_multiplier,
+                width: self.width,
+                depth: self.depth,
+            },
+            stats: terrain.stats,
+            transform: self.transform,
+            name: self.name,
+        })
+    }
+}
+
+/// A build running on the `AsyncComputeTaskPool` for the entity it is attached to. Once it
+/// finishes a `TerrainBundle` is inserted on that entity. Dropping the task cancels it, so
+/// inserting a newer task, removing this component or despawning the entity supersedes it.
+/// A request that fails `validate` is logged and its task removed without a terrain.
+#[derive(Component)]
+pub struct TerrainBuildTask(Task<Result<TerrainBuildOutput, TerrainBuildError>>);
+
+impl TerrainBuildTask {
+    pub fn spawn(request: TerrainBuildRequest) -> Self {
+        let pool = AsyncComputeTaskPool::get();
+        Self(pool.spawn(async move { request.build() }))
+    }
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TerrainBuildFinished {
+    pub entity: Entity,
+}
+
+pub fn poll_terrain_builds(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut task_q: Query<(Entity, &mut TerrainBuildTask)>,
+    mut finished: EventWriter<TerrainBuildFinished>,
+    mut stats: ResMut<TerrainBuildStats>,
+) {
+    for (entity, mut task) in task_q.iter_mut() {
+        let Some(output) = block_on(future::poll_once(&mut task.0)) else {
+            continue;
+        };
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => {
+                error!("terrain build for {entity:?} failed: {err}");
+                commands.entity(entity).remove::<TerrainBuildTask>();
+                continue;
+            }
+        };
+
+        let mut bundle =
+            TerrainBundle::with_collider(output.mesh, output.collider, &mut meshes, &mut materials);
+        bundle.transform = output.transform.into();
+        if let Some(build_stats) = output.stats {
+            stats.0.insert(output.name.to_string(), build_stats);
+        }
+        bundle.name = output.name;
+
+        commands
+            .entity(entity)
+            .insert((bundle, output.surface))
+            .remove::<TerrainBuildTask>();
+        finished.send(TerrainBuildFinished { entity });
+    }
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::{
+        TerrainBuildError, TerrainBuildRequest, TerrainBuildStats, TerrainBuildTask,
+        TerrainJobsPlugin,
+    };
+    use crate::world::{
+        config::TerrainConfig, query::TerrainSurface, terrain::TerrainColliderMode,
+    };
+    use bevy::prelude::*;
+    use std::{sync::Arc, time::Duration};
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .init_asset::<Mesh>()
+            .init_asset::<StandardMaterial>()
+            .add_plugins(TerrainJobsPlugin);
+        app
+    }
+
+    fn request() -> TerrainBuildRequest {
+        TerrainBuildRequest {
+            sampler: TerrainConfig::default().sampler(),
+            height_multiplier: 50.,
+            width: 16.,
+            depth: 16.,
+            error_threshold: 0.01,
+            lock_edges: false,
+            collider_mode: TerrainColliderMode::Heightfield,
+            coloring: None,
+            skirt_depth: None,
+            biomes: None,
+            transform: Transform::from_xyz(16., 0., 0.),
+            name: Name::new("Job Terrain"),
+        }
+    }
+
+    fn run_until_idle(app: &mut App) {
+        for _ in 0..200 {
+            app.update();
+            let mut pending = app.world_mut().query::<&TerrainBuildTask>();
+            if pending.iter(app.world()).next().is_none() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("terrain build never finished");
+    }
+
+    #[test]
+    fn finished_build_inserts_terrain() {
+        let mut app = app();
+        let entity = app
+            .world_mut()
+            .spawn(TerrainBuildTask::spawn(request()))
+            .id();
+        run_until_idle(&mut app);
+
+        let world = app.world();
+        assert!(world.get::<Handle<Mesh>>(entity).is_some());
+        assert_eq!(world.get::<TerrainSurface>(entity).unwrap().width, 16.);
+        let mesh = world.get::<Handle<Mesh>>(entity).unwrap();
+        let vertices = world
+            .resource::<Assets<Mesh>>()
+            .get(mesh)
+            .unwrap()
+            .count_vertices();
+        let stats = &world.resource::<TerrainBuildStats>().0["Job Terrain"];
+        assert_eq!(stats.vertices, vertices);
+        assert_eq!(
+            world.get::<Transform>(entity).unwrap().translation,
+            Vec3::new(16., 0., 0.)
+        );
+    }
+
+    #[test]
+    fn despawned_build_is_dropped() {
+        let mut app = app();
+        let entity = app
+            .world_mut()
+            .spawn(TerrainBuildTask::spawn(request()))
+            .id();
+        app.world_mut().despawn(entity);
+        run_until_idle(&mut app);
+
+        let mut terrains = app.world_mut().query::<&Handle<Mesh>>();
+        assert_eq!(terrains.iter(app.world()).count(), 0);
+    }
+
+    #[test]
+    fn invalid_request_is_rejected() {
+        let rect_chunk = TerrainBuildRequest {
+            depth: 8.,
+            lock_edges: true,
+            ..request()
+        };
+        assert_eq!(
+            rect_chunk.validate(),
+            Err(TerrainBuildError::ChunkNotSquare {
+                width: 16.,
+                depth: 8.
+            })
+        );
+        let partial = TerrainBuildRequest {
+            width: 16.5,
+            ..request()
+        };
+        assert!(matches!(
+            partial.validate(),
+            Err(TerrainBuildError::Extent(_))
+        ));
+        let odd_chunk = TerrainBuildRequest {
+            width: 12.,
+            depth: 12.,
+            lock_edges: true,
+            ..request()
+        };
+        assert_eq!(
+            odd_chunk.validate(),
+            Err(TerrainBuildError::ChunkNotPowerOfTwo(12.))
+        );
+
+        // the task finishes without a terrain instead of panicking on the pool
+        let mut app = app();
+        let entity = app
+            .world_mut()
+            .spawn(TerrainBuildTask::spawn(rect_chunk))
+            .id();
+        run_until_idle(&mut app);
+        assert!(app.world().get::<Handle<Mesh>>(entity).is_none());
+        assert!(app.world().resource::<TerrainBuildStats>().0.is_empty());
+    }
+}
@@ -1,24 +1,50 @@
 mod atmosphere;
+pub mod biome;
 pub mod chunks;
+pub mod config;
+pub mod deformation;
+pub mod erosion;
+pub mod heightmap;
+pub mod jobs;
 pub mod noise;
+pub mod planet;
+pub mod query;
 pub mod rtin;
+pub mod seed;
+pub mod streaming;
 pub mod terrain;
+pub mod validation;
 pub mod wfc;
 use atmosphere::SkyMaterial;
-use bevy::{color::palettes::css::YELLOW, math::NormedVectorSpace, prelude::*};
-use rtin::TerrainMeshData;
-use terrain::spawn_terrain;
+use bevy::{color::palettes::css::YELLOW, prelude::*};
+use config::{apply_terrain_config, TerrainConfigPlugin};
+use deformation::TerrainDeformationPlugin;
+use jobs::TerrainJobsPlugin;
+use query::TerrainQueryPlugin;
+use seed::{log_world_seed, WorldSeed};
+use streaming::{stream_terrain_chunks, TerrainStreamingPlugin};
 pub use wfc::heap_map::Heapable;
 
 pub struct WorldPlugin;
 
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(MaterialPlugin::<SkyMaterial>::default())
+        // a seed inserted before this plugin, like one from the command line, is kept
+        app.init_resource::<WorldSeed>()
+            .register_type::<WorldSeed>()
+            .add_plugins(MaterialPlugin::<SkyMaterial>::default())
+            .add_plugins((
+                TerrainConfigPlugin,
+                TerrainJobsPlugin,
+                TerrainStreamingPlugin,
+                TerrainQueryPlugin,
+                TerrainDeformationPlugin,
+            ))
             .add_systems(
                 Startup,
-                (atmosphere::setup_atmosphere, spawn_terrain, spawn_light).chain(),
-            );
+                (atmosphere::setup_atmosphere, spawn_light, log_world_seed).chain(),
+            )
+            .add_systems(Update, apply_terrain_config.before(stream_terrain_chunks));
     }
 }
 
@@ -39,51 +65,3 @@ fn spawn_light(mut commands: Commands) {
     };
     commands.spawn((light, Name::new("main light")));
 }
-
-fn project_to_unit_sphere(vertices: &mut Vec<Vec3>) {
-    for mut v in vertices.iter_mut() {
-        let n = v.norm();
-        *v = (1.0 / n) * *v;
-    }
-}
-
-fn icosahedron(recursion_level: usize) -> TerrainMeshData {
-    let phi = (1.0 + 5.0f32.sqrt()) * 0.5; // golden ratio
-
-    // let vertices = vec![
-    //     Vec3::new(-1.0, phi, 0.0),
-    //     Vec3::new(1.0, phi, 0.0),
-    //     Vec3::new(-1.0, -phi, 0.0),
-    //     Vec3::new(1.0, -phi, 0.0),
-    //     Vec3::new(0.0, -1.0, phi),
-    //     Vec3::new(0.0, 1.0, phi),
-    //     Vec3::new(0.0, -1.0, -phi),
-    //     Vec3::new(0.0, 1.0, -phi),
-    //     Vec3::new(phi, 0.0, -1.0),
-    //     Vec3::new(phi, 0.0, 1.0),
-    //     Vec3::new(-phi, 0.0, -1.0),
-    //     Vec3::new(-phi, 0.0, 1.0),
-    // ];
-    let vertices = vec![
-        Vec3::new(1.0, -phi, 0.0),  // Negated (-1.0, phi, 0.0)
-        Vec3::new(-1.0, -phi, 0.0), // Negated (1.0, phi, 0.0)
-        Vec3::new(1.0, phi, 0.0),   // Negated (-1.0, -phi, 0.0)
-        Vec3::new(-1.0, phi, 0.0),  // Negated (1.0, -phi, 0.0)
-        Vec3::new(0.0, 1.0, -phi),  // Negated (0.0, -1.0, phi)
-        Vec3::new(0.0, -1.0, -phi), // Negated (0.0, 1.0, phi)
-        Vec3::new(0.0, 1.0, phi),   // Negated (0.0, -1.0, -phi)
-        Vec3::new(0.0, -1.0, phi),  // Negated (0.0, 1.0, -phi)
-        Vec3::new(-phi, 0.0, 1.0),  // Negated (phi, 0.0, -1.0)
-        Vec3::new(-phi, 0.0, -1.0), // Negated (phi, 0.0, 1.0)
-        Vec3::new(phi, 0.0, 1.0),   // Negated (-phi, 0.0, -1.0)
-        Vec3::new(phi, 0.0, -1.0),  // Negated (-phi, 0.0, 1.0)
-    ];
-
-    let indices = vec![
-        0, 11, 5, 0, 5, 1, 0, 1, 7, 0, 7, 10, 0, 10, 11, 1, 5, 9, 5, 11, 4, 11, 10, 2, 10, 7, 6, 7,
-        1, 8, 3, 9, 4, 3, 4, 2, 3, 2, 6, 3, 6, 8, 3, 8, 9, 4, 9, 5, 2, 4, 11, 6, 2, 10, 8, 6, 7, 9,
-        8, 1,
-    ];
-
-    TerrainMeshData { vertices, indices }
-}
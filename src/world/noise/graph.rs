@@ -0,0 +1,490 @@
+use crate::world::rtin::PlaneSampler;
+use noise::{
+    core::worley::{distance_functions::euclidean, worley_2d, ReturnType},
+    permutationtable::PermutationTable,
+    Billow, Fbm, MultiFractal, NoiseFn, Perlin, RidgedMulti, Simplex, Vector2,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NoiseKind {
+    Perlin,
+    Simplex,
+    /// Cellular noise, the value of the closest feature point
+    Worley,
+    Fbm,
+    /// Fbm of absolute values, puffy rounded hills
+    Billow,
+    /// Sharp ridges where the noise crosses 0, for mountain ranges
+    RidgedMulti,
+}
+
+/// A single noise function, sampled at `frequency * (x, y)` and returned as
+/// `value * weight + offset`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NoiseLayer {
+    pub kind: NoiseKind,
+    pub seed: u32,
+    pub frequency: f32,
+    pub weight: f32,
+    pub offset: f32,
+    /// Only used by the fractal kinds
+    pub octaves: usize,
+    pub lacunarity: f32,
+    pub persistence: f32,
+}
+
+impl Default for NoiseLayer {
+    fn default() -> Self {
+        Self {
+            kind: NoiseKind::Perlin,
+            seed: 0,
+            frequency: 0.01,
+            weight: 1.,
+            offset: 0.,
+            octaves: 6,
+            lacunarity: 2.,
+            persistence: 0.5,
+        }
+    }
+}
+
+impl NoiseLayer {
+    pub fn new(kind: NoiseKind, seed: u32) -> Self {
+        Self {
+            kind,
+            seed,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_frequency(mut self, frequency: f32) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    pub fn with_weight(mut self, weight: f32, offset: f32) -> Self {
+        self.weight = weight;
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_octaves(mut self, octaves: usize) -> Self {
+        self.octaves = octaves;
+        self
+    }
+
+    fn build(&self) -> Box<dyn NoiseFn<f64, 2> + Send + Sync> {
+        // the frequency is applied to the sample point for every kind, so fractals start at 1
+        match self.kind {
+            NoiseKind::Perlin => Box::new(Perlin::new(self.seed)),
+            NoiseKind::Simplex => Box::new(Simplex::new(self.seed)),
+            NoiseKind::Worley => Box::new(CellNoise(PermutationTable::new(self.seed))),
+            NoiseKind::Fbm => Box::new(self.fractal(Fbm::<Perlin>::new(self.seed))),
+            NoiseKind::Billow => Box::new(self.fractal(Billow::<Perlin>::new(self.seed))),
+            NoiseKind::RidgedMulti => Box::new(self.fractal(RidgedMulti::<Perlin>::new(self.seed))),
+        }
+    }
+
+    fn fractal<T: MultiFractal>(&self, noise: T) -> T {
+        noise
+            .set_octaves(self.octaves)
+            .set_frequency(1.)
+            .set_lacunarity(self.lacunarity as f64)
+            .set_persistence(self.persistence as f64)
+    }
+}
+
+/// Euclidean worley noise returning the closest cell's value. `noise::Worley` keeps its
+/// distance function in an `Rc`, so it can't be shared with the build tasks.
+struct CellNoise(PermutationTable);
+
+impl NoiseFn<f64, 2> for CellNoise {
+    fn get(&self, point: [f64; 2]) -> f64 {
+        worley_2d(&self.0, euclidean, ReturnType::Value, Vector2::from(point))
+    }
+}
+
+/// Description of a sampler graph. Leaves are noise layers and constants, every other node
+/// combines or reshapes the values of its inputs at the same point. Build a `NoiseGraph` from
+/// it to sample it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum NoiseNode {
+    Layer(NoiseLayer),
+    Constant(f32),
+    Add(Vec<NoiseNode>),
+    Multiply(Vec<NoiseNode>),
+    Min(Vec<NoiseNode>),
+    Max(Vec<NoiseNode>),
+    /// `a` where `mask` is 0 and `b` where it is 1, the mask is clamped to [0, 1]
+    Lerp {
+        a: Box<NoiseNode>,
+        b: Box<NoiseNode>,
+        mask: Box<NoiseNode>,
+    },
+    Clamp {
+        input: Box<NoiseNode>,
+        min: f32,
+        max: f32,
+    },
+    /// Flattens the input into steps between the given heights, easing from one to the next
+    Terrace {
+        input: Box<NoiseNode>,
+        steps: Vec<f32>,
+    },
+    /// Piecewise linear remap through `[input, output]` points, sorted by input
+    Curve {
+        input: Box<NoiseNode>,
+        points: Vec<[f32; 2]>,
+    },
+    Abs(Box<NoiseNode>),
+    /// Samples `input` at the point moved by `strength * (x, y)`
+    Warp {
+        input: Box<NoiseNode>,
+        x: Box<NoiseNode>,
+        y: Box<NoiseNode>,
+        strength: f32,
+    },
+}
+
+impl NoiseNode {
+    pub fn lerp(a: NoiseNode, b: NoiseNode, mask: NoiseNode) -> Self {
+        Self::Lerp {
+            a: Box::new(a),
+            b: Box::new(b),
+            mask: Box::new(mask),
+        }
+    }
+
+    pub fn clamp(self, min: f32, max: f32) -> Self {
+        Self::Clamp {
+            input: Box::new(self),
+            min,
+            max,
+        }
+    }
+
+    pub fn terrace(self, steps: Vec<f32>) -> Self {
+        Self::Terrace {
+            input: Box::new(self),
+            steps,
+        }
+    }
+
+    pub fn curve(self, points: Vec<[f32; 2]>) -> Self {
+        Self::Curve {
+            input: Box::new(self),
+            points,
+        }
+    }
+
+    pub fn abs(self) -> Self {
+        Self::Abs(Box::new(self))
+    }
+
+    pub fn warp(self, x: NoiseNode, y: NoiseNode, strength: f32) -> Self {
+        Self::Warp {
+            input: Box::new(self),
+            x: Box::new(x),
+            y: Box::new(y),
+            strength,
+        }
+    }
+
+    /// Adds `seed` to the seed of every layer, so each layer's own seed becomes an offset
+    /// and layers stay different from each other
+    pub fn reseed(&mut self, seed: u32) {
+        match self {
+            Self::Layer(layer) => layer.seed = layer.seed.wrapping_add(seed),
+            Self::Constant(_) => {}
+            Self::Add(nodes) | Self::Multiply(nodes) | Self::Min(nodes) | Self::Max(nodes) => {
+                nodes.iter_mut().for_each(|node| node.reseed(seed))
+            }
+            Self::Clamp { input, .. }
+            | Self::Terrace { input, .. }
+            | Self::Curve { input, .. }
+            | Self::Abs(input) => input.reseed(seed),
+            Self::Lerp {
+                a: first,
+                b: second,
+                mask: third,
+            }
+            | Self::Warp {
+                input: first,
+                x: second,
+                y: third,
+                ..
+            } => {
+                first.reseed(seed);
+                second.reseed(seed);
+                third.reseed(seed);
+            }
+        }
+    }
+}
+
+impl From<NoiseLayer> for NoiseNode {
+    fn from(value: NoiseLayer) -> Self {
+        Self::Layer(value)
+    }
+}
+
+/// Same shape as `NoiseNode`, with the noise functions built once up front
+enum Compiled {
+    Layer {
+        noise: Box<dyn NoiseFn<f64, 2> + Send + Sync>,
+        frequency: f64,
+        weight: f32,
+        offset: f32,
+    },
+    Constant(f32),
+    Add(Vec<Compiled>),
+    Multiply(Vec<Compiled>),
+    Min(Vec<Compiled>),
+    Max(Vec<Compiled>),
+    Lerp(Box<[Compiled; 3]>),
+    Clamp(Box<Compiled>, f32, f32),
+    Terrace(Box<Compiled>, Vec<f32>),
+    Curve(Box<Compiled>, Vec<[f32; 2]>),
+    Abs(Box<Compiled>),
+    Warp(Box<[Compiled; 3]>, f32),
+}
+
+impl Compiled {
+    fn new(node: &NoiseNode) -> Self {
+        let boxed = |node: &NoiseNode| Box::new(Self::new(node));
+        let all = |nodes: &Vec<NoiseNode>| nodes.iter().map(Self::new).collect();
+        match node {
+            NoiseNode::Layer(layer) => Self::Layer {
+                noise: layer.build(),
+                frequency: layer.frequency as f64,
+                weight: layer.weight,
+                offset: layer.offset,
+            },
+            NoiseNode::Constant(value) => Self::Constant(*value),
+            NoiseNode::Add(nodes) => Self::Add(all(nodes)),
+            NoiseNode::Multiply(nodes) => Self::Multiply(all(nodes)),
+            NoiseNode::Min(nodes) => Self::Min(all(nodes)),
+            NoiseNode::Max(nodes) => Self::Max(all(nodes)),
+            NoiseNode::Lerp { a, b, mask } => {
+                Self::Lerp(Box::new([Self::new(a), Self::new(b), Self::new(mask)]))
+            }
+            NoiseNode::Clamp { input, min, max } => Self::Clamp(boxed(input), *min, *max),
+            NoiseNode::Terrace { input, steps } => {
+                let mut steps = steps.clone();
+                steps.sort_by(f32::total_cmp);
+                Self::Terrace(boxed(input), steps)
+            }
+            NoiseNode::Curve { input, points } => {
+                let mut points = points.clone();
+                points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+                Self::Curve(boxed(input), points)
+            }
+            NoiseNode::Abs(input) => Self::Abs(boxed(input)),
+            NoiseNode::Warp {
+                input,
+                x,
+                y,
+                strength,
+            } => Self::Warp(
+                Box::new([Self::new(input), Self::new(x), Self::new(y)]),
+                *strength,
+            ),
+        }
+    }
+
+    fn get(&self, x: f64, y: f64) -> f32 {
+        match self {
+            Self::Layer {
+                noise,
+                frequency,
+                weight,
+                offset,
+            } => noise.get([x * frequency, y * frequency]) as f32 * weight + offset,
+            Self::Constant(value) => *value,
+            Self::Add(nodes) => nodes.iter().map(|n| n.get(x, y)).sum(),
+            Self::Multiply(nodes) => nodes.iter().map(|n| n.get(x, y)).product(),
+            Self::Min(nodes) => nodes
+                .iter()
+                .map(|n| n.get(x, y))
+                .reduce(f32::min)
+                .unwrap_or(0.),
+            Self::Max(nodes) => nodes
+                .iter()
+                .map(|n| n.get(x, y))
+                .reduce(f32::max)
+                .unwrap_or(0.),
+            Self::Lerp(nodes) => {
+                let [a, b, mask] = nodes.as_ref();
+                let t = mask.get(x, y).clamp(0., 1.);
+                a.get(x, y) * (1. - t) + b.get(x, y) * t
+            }
+            Self::Clamp(input, min, max) => input.get(x, y).clamp(*min, *max),
+            Self::Terrace(input, steps) => terrace(input.get(x, y), steps),
+            Self::Curve(input, points) => curve(input.get(x, y), points),
+            Self::Abs(input) => input.get(x, y).abs(),
+            Self::Warp(nodes, strength) => {
+                let [input, warp_x, warp_y] = nodes.as_ref();
+                let strength = *strength as f64;
+                input.get(
+                    x + warp_x.get(x, y) as f64 * strength,
+                    y + warp_y.get(x, y) as f64 * strength,
+                )
+            }
+        }
+    }
+}
+
+fn terrace(value: f32, steps: &[f32]) -> f32 {
+    let Some(upper) = steps.iter().position(|s| *s > value) else {
+        return steps.last().copied().unwrap_or(value);
+    };
+    if upper == 0 {
+        return steps[0];
+    }
+    let (low, high) = (steps[upper - 1], steps[upper]);
+    let t = (value - low) / (high - low);
+    // flat just above a step, steep just below the next one
+    low + (high - low) * t * t
+}
+
+fn curve(value: f32, points: &[[f32; 2]]) -> f32 {
+    let Some(upper) = points.iter().position(|p| p[0] > value) else {
+        return points.last().map(|p| p[1]).unwrap_or(value);
+    };
+    if upper == 0 {
+        return points[0][1];
+    }
+    let ([x0, y0], [x1, y1]) = (points[upper - 1], points[upper]);
+    y0 + (y1 - y0) * (value - x0) / (x1 - x0)
+}
+
+/// Samples a `NoiseNode` graph
+pub struct NoiseGraph {
+    node: NoiseNode,
+    compiled: Compiled,
+}
+
+impl NoiseGraph {
+    pub fn new(node: impl Into<NoiseNode>) -> Self {
+        let node = node.into();
+        let compiled = Compiled::new(&node);
+        Self { node, compiled }
+    }
+
+    pub fn node(&self) -> &NoiseNode {
+        &self.node
+    }
+}
+
+impl std::fmt::Debug for NoiseGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("NoiseGraph").field(&self.node).finish()
+    }
+}
+
+impl PlaneSampler for NoiseGraph {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        self.compiled.get(x as f64, y as f64)
+    }
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::{NoiseGraph, NoiseKind, NoiseLayer, NoiseNode};
+    use crate::world::rtin::PlaneSampler;
+    use noise::{NoiseFn, Perlin};
+
+    fn sample(node: NoiseNode) -> f32 {
+        NoiseGraph::new(node).get(3., 4.)
+    }
+
+    #[test]
+    fn layer_weight_offset_frequency() {
+        let layer = NoiseLayer::new(NoiseKind::Perlin, 9)
+            .with_frequency(0.1)
+            .with_weight(2., 1.);
+        let frequency = 0.1f32 as f64;
+        let perlin = Perlin::new(9).get([3. * frequency, 4. * frequency]) as f32;
+        assert_eq!(sample(layer.into()), perlin * 2. + 1.);
+    }
+
+    #[test]
+    fn combinators() {
+        use NoiseNode::Constant;
+        assert_eq!(sample(NoiseNode::Add(vec![Constant(1.), Constant(2.)])), 3.);
+        assert_eq!(
+            sample(NoiseNode::Multiply(vec![Constant(3.), Constant(2.)])),
+            6.
+        );
+        assert_eq!(
+            sample(NoiseNode::Min(vec![Constant(3.), Constant(-2.)])),
+            -2.
+        );
+        assert_eq!(
+            sample(NoiseNode::Max(vec![Constant(3.), Constant(-2.)])),
+            3.
+        );
+        let lerp = NoiseNode::lerp(Constant(0.), Constant(10.), Constant(0.25));
+        assert_eq!(sample(lerp), 2.5);
+        let clamped_mask = NoiseNode::lerp(Constant(0.), Constant(10.), Constant(4.));
+        assert_eq!(sample(clamped_mask), 10.);
+    }
+
+    #[test]
+    fn filters() {
+        use NoiseNode::Constant;
+        assert_eq!(sample(Constant(5.).clamp(-1., 1.)), 1.);
+        assert_eq!(sample(Constant(-0.5).abs()), 0.5);
+
+        let steps = vec![1., 0., 2.];
+        assert_eq!(sample(Constant(1.5).terrace(steps.clone())), 1.25);
+        assert_eq!(sample(Constant(1.).terrace(steps.clone())), 1.);
+        assert_eq!(sample(Constant(-3.).terrace(steps.clone())), 0.);
+        assert_eq!(sample(Constant(3.).terrace(steps)), 2.);
+
+        let points = vec![[0., 0.], [1., 4.]];
+        assert_eq!(sample(Constant(0.5).curve(points.clone())), 2.);
+        assert_eq!(sample(Constant(2.).curve(points)), 4.);
+    }
+
+    #[test]
+    fn warp_moves_sample_point() {
+        let layer: NoiseNode = NoiseLayer::new(NoiseKind::Simplex, 2).into();
+        let warped = layer
+            .clone()
+            .warp(NoiseNode::Constant(1.), NoiseNode::Constant(-0.5), 4.);
+        let plain = NoiseGraph::new(layer);
+        assert_eq!(NoiseGraph::new(warped).get(3., 4.), plain.get(7., 2.));
+    }
+
+    #[test]
+    fn reseed_offsets_every_layer() {
+        let layer = |seed| NoiseNode::Layer(NoiseLayer::new(NoiseKind::Perlin, seed));
+        let mut node = NoiseNode::lerp(layer(1), layer(2), layer(3).abs());
+        node.reseed(10);
+        assert_eq!(node, NoiseNode::lerp(layer(11), layer(12), layer(13).abs()));
+    }
+
+    #[test]
+    fn ridged_mountains_with_plateaus() {
+        let mountains = NoiseLayer::new(NoiseKind::RidgedMulti, 1).with_octaves(4);
+        let hills = NoiseLayer::new(NoiseKind::Billow, 2).with_weight(0.3, 0.);
+        let mask = NoiseLayer::new(NoiseKind::Worley, 3)
+            .with_frequency(0.005)
+            .with_weight(0.5, 0.5);
+        let graph = NoiseGraph::new(
+            NoiseNode::lerp(hills.into(), mountains.into(), mask.into())
+                .clamp(-0.6, 0.6)
+                .terrace(vec![-0.6, -0.2, 0.3, 0.6]),
+        );
+
+        for x in 0..32 {
+            for y in 0..32 {
+                let h = graph.get(x as f32 * 7.3, y as f32 * 5.1);
+                assert!((-0.6..=0.6).contains(&h), "{h} out of the plateau range");
+            }
+        }
+    }
+}
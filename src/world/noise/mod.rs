@@ -1,3 +1,4 @@
+pub mod graph;
 use super::rtin::PlaneSampler;
 use bevy::prelude::Resource;
 use noise::{Fbm, NoiseFn, Perlin};
@@ -0,0 +1,177 @@
+use super::rtin::TerrainMeshData;
+use bevy::{prelude::*, utils::HashMap};
+use bevy_rapier3d::prelude::Collider;
+use noise::NoiseFn;
+
+/// Height of a point on a planet's surface, the 3D counterpart of `PlaneSampler`
+pub trait SphereSampler: Send + Sync {
+    fn get(&self, point: Vec3) -> f32;
+}
+
+impl<T: NoiseFn<f64, 3> + Send + Sync> SphereSampler for T {
+    fn get(&self, point: Vec3) -> f32 {
+        NoiseFn::get(self, [point.x as f64, point.y as f64, point.z as f64]) as f32
+    }
+}
+
+fn project_to_unit_sphere(vertices: &mut [Vec3]) {
+    for v in vertices.iter_mut() {
+        *v = v.normalize();
+    }
+}
+
+/// Icosahedron on the unit sphere with every face split in 4, `recursion_level` times. New
+/// vertices are pushed out to the sphere, and faces wind counter clockwise seen from outside.
+pub fn icosahedron(recursion_level: usize) -> TerrainMeshData {
+    let phi = (1.0 + 5.0f32.sqrt()) * 0.5; // golden ratio
+
+    let mut vertices = vec![
+        Vec3::new(-1.0, phi, 0.0),
+        Vec3::new(1.0, phi, 0.0),
+        Vec3::new(-1.0, -phi, 0.0),
+        Vec3::new(1.0, -phi, 0.0),
+        Vec3::new(0.0, -1.0, phi),
+        Vec3::new(0.0, 1.0, phi),
+        Vec3::new(0.0, -1.0, -phi),
+        Vec3::new(0.0, 1.0, -phi),
+        Vec3::new(phi, 0.0, -1.0),
+        Vec3::new(phi, 0.0, 1.0),
+        Vec3::new(-phi, 0.0, -1.0),
+        Vec3::new(-phi, 0.0, 1.0),
+    ];
+    project_to_unit_sphere(&mut vertices);
+
+    let mut indices = vec![
+        0, 11, 5, 0, 5, 1, 0, 1, 7, 0, 7, 10, 0, 10, 11, 1, 5, 9, 5, 11, 4, 11, 10, 2, 10, 7, 6, 7,
+        1, 8, 3, 9, 4, 3, 4, 2, 3, 2, 6, 3, 6, 8, 3, 8, 9, 4, 9, 5, 2, 4, 11, 6, 2, 10, 8, 6, 7, 9,
+        8, 1,
+    ];
+
+    for _ in 0..recursion_level {
+        // edges are shared by two faces, both have to reuse the same midpoint
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32, vertices: &mut Vec<Vec3>| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let v = (vertices[a as usize] + vertices[b as usize]).normalize();
+                vertices.push(v);
+                vertices.len() as u32 - 1
+            })
+        };
+
+        let mut next = Vec::with_capacity(indices.len() * 4);
+        for face in indices.chunks_exact(3) {
+            let (a, b, c) = (face[0], face[1], face[2]);
+            let ab = midpoint(a, b, &mut vertices);
+            let bc = midpoint(b, c, &mut vertices);
+            let ca = midpoint(c, a, &mut vertices);
+            next.extend([a, ab, ca, b, bc, ab, c, ca, bc, ab, bc, ca]);
+        }
+        indices = next;
+    }
+
+    TerrainMeshData {
+        vertices,
+        indices,
+        normals: vec![],
+        stats: None,
+    }
+}
+
+/// Subdivided icosahedron of `radius` with every vertex moved along its direction by
+/// `sampler * height_multiplier`. The sampler is read on the undisplaced surface, so noise
+/// frequencies are in world units like on flat terrain. Normals are left to the mesh.
+pub fn build_planet(
+    sampler: &impl SphereSampler,
+    radius: f32,
+    height_multiplier: f32,
+    recursion_level: usize,
+) -> TerrainMeshData {
+    let mut planet = icosahedron(recursion_level);
+    for v in planet.vertices.iter_mut() {
+        let height = radius + sampler.get(*v * radius) * height_multiplier;
+        *v *= height;
+    }
+    planet
+}
+
+/// Trimesh with the same triangles as the planet's render mesh
+pub fn planet_collider(planet: &TerrainMeshData) -> Collider {
+    let indices = planet
+        .indices
+        .chunks_exact(3)
+        .map(|face| [face[0], face[1], face[2]])
+        .collect();
+    Collider::trimesh(planet.vertices.clone(), indices)
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::{build_planet, icosahedron, planet_collider, SphereSampler};
+    use bevy::math::{Quat, Vec3};
+    use noise::{Fbm, Perlin};
+
+    struct Flat(f32);
+
+    impl SphereSampler for Flat {
+        fn get(&self, _point: Vec3) -> f32 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn subdivision_counts() {
+        for level in 0..4 {
+            let sphere = icosahedron(level);
+            assert_eq!(sphere.vertices.len(), 10 * 4usize.pow(level as u32) + 2);
+            assert_eq!(sphere.indices.len(), 3 * 20 * 4usize.pow(level as u32));
+            assert!(sphere
+                .vertices
+                .iter()
+                .all(|v| (v.length() - 1.).abs() < 1e-5));
+        }
+    }
+
+    #[test]
+    fn faces_wind_outwards() {
+        let sphere = icosahedron(2);
+        for face in sphere.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| sphere.vertices[face[i] as usize]);
+            assert!((b - a).cross(c - a).dot(a + b + c) > 0.);
+        }
+    }
+
+    #[test]
+    fn displaced_along_direction() {
+        let flat = build_planet(&Flat(0.5), 10., 4., 2);
+        assert!(flat
+            .vertices
+            .iter()
+            .all(|v| (v.length() - 12.).abs() < 1e-4));
+
+        let noise = Fbm::<Perlin>::new(3);
+        let planet = build_planet(&noise, 10., 2., 2);
+        let sphere = icosahedron(2);
+        for (v, direction) in planet.vertices.iter().zip(sphere.vertices.iter()) {
+            let expected = 10. + noise.get(*direction * 10.) * 2.;
+            assert!((v.length() - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn collider_matches_surface() {
+        let planet = build_planet(&Flat(0.), 10., 1., 3);
+        let collider = planet_collider(&planet);
+        let toi = collider
+            .cast_ray(
+                Vec3::ZERO,
+                Quat::IDENTITY,
+                Vec3::X * 20.,
+                -Vec3::X,
+                20.,
+                true,
+            )
+            .expect("ray should hit the planet");
+        // flat faces sit a little inside the sphere through their vertices
+        assert!((20. - toi) <= 10. && (20. - toi) > 9.9);
+    }
+}
@@ -0,0 +1,193 @@
+use super::{
+    rtin::{sampler_normal, PlaneSampler},
+    terrain::Terrain,
+};
+use bevy::{ecs::system::SystemParam, math::Vec3Swizzles, prelude::*};
+use bevy_rapier3d::prelude::Velocity;
+use std::sync::Arc;
+
+pub struct TerrainQueryPlugin;
+
+impl Plugin for TerrainQueryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, snap_to_terrain);
+    }
+}
+
+/// What a finished terrain entity was built from, so its surface can be queried without
+/// reading back the mesh. The sampler works in the entity's local grid coordinates.
+#[derive(Component, Clone)]
+pub struct TerrainSurface {
+    pub sampler: Arc<dyn PlaneSampler>,
+    pub height_multiplier: f32,
+    pub width: f32,
+    pub depth: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainSample {
+    /// World space height of the ground
+    pub height: f32,
+    pub normal: Vec3,
+    /// Angle between the normal and straight up, in radians
+    pub slope: f32,
+}
+
+impl TerrainSurface {
+    /// Sample at a position relative to the terrain's origin, `None` outside its extent
+    pub fn sample_local(&self, local: Vec2) -> Option<TerrainSample> {
+        if local.x < 0. || local.y < 0. || local.x > self.width || local.y > self.depth {
+            return None;
+        }
+        let normal = sampler_normal(&self.sampler, local.x, local.y, self.height_multiplier);
+        Some(TerrainSample {
+            height: self.sampler.get(local.x, local.y) * self.height_multiplier,
+            normal,
+            slope: normal.angle_between(Vec3::Y),
+        })
+    }
+}
+
+/// Ground height, normal and slope at any world XZ covered by loaded terrain. Heights come
+/// straight from the sampler, so they match the heightfield collider rather than the
+/// simplified render mesh. Terrain entities are expected to be top level and unrotated.
+#[derive(SystemParam)]
+pub struct TerrainQuery<'w, 's> {
+    terrain_q: Query<'w, 's, (&'static TerrainSurface, &'static Transform), With<Terrain>>,
+}
+
+impl<'w, 's> TerrainQuery<'w, 's> {
+    pub fn sample(&self, position: Vec2) -> Option<TerrainSample> {
+        // neighbouring chunks share their edges, so whichever is found first is fine
+        self.terrain_q.iter().find_map(|(surface, transform)| {
+            let origin = transform.translation;
+            let mut sample = surface.sample_local(position - origin.xz())?;
+            sample.height += origin.y;
+            Some(sample)
+        })
+    }
+
+    pub fn height(&self, position: Vec2) -> Option<f32> {
+        self.sample(position).map(|s| s.height)
+    }
+
+    pub fn normal(&self, position: Vec2) -> Option<Vec3> {
+        self.sample(position).map(|s| s.normal)
+    }
+
+    pub fn slope(&self, position: Vec2) -> Option<f32> {
+        self.sample(position).map(|s| s.slope)
+    }
+}
+
+/// Moves the entity to `offset` above the ground once the terrain under it has loaded, then
+/// removes itself. Any velocity picked up while waiting is cleared.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct SnapToTerrain {
+    pub offset: f32,
+}
+
+pub fn snap_to_terrain(
+    mut commands: Commands,
+    terrain: TerrainQuery,
+    mut snap_q: Query<(
+        Entity,
+        &SnapToTerrain,
+        &mut Transform,
+        Option<&mut Velocity>,
+    )>,
+) {
+    for (entity, snap, mut transform, velocity) in snap_q.iter_mut() {
+        let Some(height) = terrain.height(transform.translation.xz()) else {
+            continue;
+        };
+        transform.translation.y = height + snap.offset;
+        if let Some(mut velocity) = velocity {
+            *velocity = Velocity::zero();
+        }
+        commands.entity(entity).remove::<SnapToTerrain>();
+    }
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::{TerrainQuery, TerrainSurface};
+    use crate::world::{
+        noise::NoiseSampler,
+        rtin::{OffsetSampler, PlaneSampler},
+        terrain::Terrain,
+    };
+    use bevy::{ecs::system::SystemState, prelude::*};
+    use noise::{Fbm, Perlin};
+    use std::sync::Arc;
+
+    struct SlopeSampler;
+
+    impl PlaneSampler for SlopeSampler {
+        fn get(&self, x: f32, _y: f32) -> f32 {
+            x
+        }
+    }
+
+    #[test]
+    fn sample_local_on_slope() {
+        let surface = TerrainSurface {
+            sampler: Arc::new(SlopeSampler),
+            height_multiplier: 1.,
+            width: 16.,
+            depth: 8.,
+        };
+        let sample = surface.sample_local(Vec2::new(4., 4.)).unwrap();
+        assert_eq!(sample.height, 4.);
+        assert!((sample.slope - std::f32::consts::FRAC_PI_4).abs() < 1e-5);
+        assert!(sample
+            .normal
+            .abs_diff_eq(Vec3::new(-1., 1., 0.).normalize(), 1e-5));
+
+        assert!(surface.sample_local(Vec2::new(16., 8.)).is_some());
+        assert!(surface.sample_local(Vec2::new(4., 9.)).is_none());
+        assert!(surface.sample_local(Vec2::new(-0.1, 4.)).is_none());
+    }
+
+    #[test]
+    fn continuous_across_chunks() {
+        let mut noise = Fbm::<Perlin>::new(1);
+        noise.frequency = 0.1;
+        let world: Arc<dyn PlaneSampler> = Arc::new(NoiseSampler::single_layer(noise));
+        let mut app = App::new();
+        // two chunks side by side along x, built the way streamed chunks are
+        for x in [0., 16.] {
+            app.world_mut().spawn((
+                Terrain,
+                TerrainSurface {
+                    sampler: Arc::new(OffsetSampler::new(world.clone(), Vec2::new(x, 0.))),
+                    height_multiplier: 10.,
+                    width: 16.,
+                    depth: 16.,
+                },
+                Transform::from_xyz(x, -2., 0.),
+            ));
+        }
+        let mut state = SystemState::<TerrainQuery>::new(app.world_mut());
+        let query = state.get(app.world());
+
+        for z in [0., 3.5, 16.] {
+            let edge = query.sample(Vec2::new(16., z)).unwrap();
+            assert!((edge.height - (world.get(16., z) * 10. - 2.)).abs() < 1e-4);
+            for x in [16. - 1e-3, 16. + 1e-3] {
+                let beside = query.sample(Vec2::new(x, z)).unwrap();
+                assert!((beside.height - edge.height).abs() < 1e-2);
+                assert!(beside.normal.abs_diff_eq(edge.normal, 1e-2));
+            }
+        }
+        assert_eq!(
+            query.height(Vec2::new(20., 5.)),
+            Some(world.get(20., 5.) * 10. - 2.)
+        );
+
+        assert!(query.sample(Vec2::new(32.5, 4.)).is_none());
+        assert!(query.sample(Vec2::new(-0.5, 4.)).is_none());
+        assert!(query.sample(Vec2::new(8., -1.)).is_none());
+        assert!(query.sample(Vec2::new(24., 17.)).is_none());
+    }
+}
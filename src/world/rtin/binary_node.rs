@@ -2,21 +2,21 @@ use bevy::prelude::Triangle2d;
 use bevy_tnua::math::Vector2;
 //
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct BinaryNode(u32);
+pub struct BinaryNode(u64);
 
-impl From<u32> for BinaryNode {
+impl From<u64> for BinaryNode {
     ///         1
     ///        / \
     ///       2   3
-    fn from(value: u32) -> Self {
+    fn from(value: u64) -> Self {
         if value <= 1 {
             panic!("THIS BINARY TREE STARTS AT 2");
         }
         Self(value)
     }
 }
-impl AsRef<u32> for BinaryNode {
-    fn as_ref(&self) -> &u32 {
+impl AsRef<u64> for BinaryNode {
+    fn as_ref(&self) -> &u64 {
         &self.0
     }
 }
@@ -24,26 +24,19 @@ impl AsRef<u32> for BinaryNode {
 /// Most significant bit. Finds first with a value of 1 in binary
 /// following implementation of this video: https://www.youtube.com/watch?v=2-zmWlM5XSE
 /// Returns bit POSITION, so 1000 should return 4, not 8
-pub fn msb(mut val: u32) -> u32 {
-    // let mut intervals = vec![1, 2, 4, 8, 16];
-    // intervals.reverse();
-    // while let Some(interval) = intervals.pop() {
-    //     val |= val >> interval;
-    // }
-    // val += 1;
-    // val.trailing_zeros()
-    32 - val.leading_zeros()
+pub fn msb(val: u64) -> u32 {
+    u64::BITS - val.leading_zeros()
 }
 
 impl BinaryNode {
     // Assuming root node is level 0
-    fn level(&self) -> u32 {
+    pub fn level(&self) -> u32 {
         let msb = msb(self.0);
         // YOU CHANGED THIS 2 FROM A 1
         msb - 1
     }
 
-    fn index_in_level(&self) -> u32 {
+    fn index_in_level(&self) -> u64 {
         self.as_ref() - (1 << (msb(*self.as_ref()) - 1))
     }
 
@@ -56,28 +49,19 @@ impl BinaryNode {
         (left.into(), right.into())
     }
 
-    pub fn level_start_index(level: u32) -> u32 {
-        ((2 << level) - 1) & (!1u32)
+    pub fn level_start_index(level: u32) -> u64 {
+        ((2u64 << level) - 1) & (!1u64)
     }
 
-    pub fn from_triangle_index(idx: u32) -> Self {
-        let mut level = 0;
-        let mut index_level_start = 0;
-
-        for i in 0..32 {
-            let new_index_level_start = Self::level_start_index(i);
-            if idx >= new_index_level_start {
-                level = i;
-                index_level_start = new_index_level_start;
-            } else {
-                break;
-            }
-        }
+    pub fn from_triangle_index(idx: u64) -> Self {
+        // levels start at 2^(level + 1) - 2, so the level is read straight off idx + 2
+        let level = msb(idx + 2) - 2;
+        let index_level_start = Self::level_start_index(level);
 
         ((1 << (level + 1)) + (idx - index_level_start)).into()
     }
 
-    pub fn triangle_index(&self) -> u32 {
+    pub fn triangle_index(&self) -> u64 {
         let level = self.level() - 1;
         let index_level_start = Self::level_start_index(level);
         let index_in_level = self.index_in_level();
@@ -88,10 +72,10 @@ impl BinaryNode {
     /// We only need the x and y values because we will be mapping these over 2d noise
     /// So, given a grid size we can return the coordinates on a triangle given our constraints
     pub fn triangle_coords(&self, grid_size: f32) -> Triangle2d {
-        let left_steps = self.steps_to_node();
+        let mut left_steps = self.steps();
         let last_pos = grid_size - 1. as f32;
 
-        let (mut a, mut b, mut c) = if left_steps[0] {
+        let (mut a, mut b, mut c) = if left_steps.next().unwrap() {
             (
                 Vector2::new(last_pos, last_pos),
                 Vector2::new(0., 0.),
@@ -105,8 +89,8 @@ impl BinaryNode {
             )
         };
 
-        for left_step in left_steps.iter().skip(1) {
-            if *left_step {
+        for left_step in left_steps {
+            if left_step {
                 let (new_a, new_b, new_c) = (c, a, (a + b) / 2.);
                 a = new_a;
                 b = new_b;
@@ -126,26 +110,16 @@ impl BinaryNode {
 
     /// When we find the coordinates of a triangle we need to go through our triangles, iteratively
     /// splitting them in half and keep track of which triangle our binID is correlated with
-    /// each step marked as true if it is a left step when going down from the root
-    fn steps_to_node(&self) -> Vec<bool> {
-        let mut steps = vec![false; self.level() as usize];
-        let mut val = self.as_ref().clone();
-
-        let mut i = 0;
-        while val > 1 {
-            if val % 2 == 0 {
-                steps[(self.level() as usize - 1) - i] = true;
-            }
-            val /= 2;
-            i += 1;
-        }
-        steps
+    /// each step marked as true if it is a left step when going down from the root.
+    /// The steps are the bits of the id below its leading 1, a 0 bit being a left step.
+    fn steps(&self) -> impl Iterator<Item = bool> {
+        let id = self.0;
+        let level = self.level();
+        (0..level).map(move |depth| (id >> (level - 1 - depth)) & 1 == 0)
     }
 
     pub fn errors_vec_index(&self, grid_size: f32) -> usize {
-        let triangle_midpoint = self.midpoint_pixel_coords(grid_size);
-        let midpoint_error_vec_index = triangle_midpoint[1] * grid_size + triangle_midpoint[0];
-        midpoint_error_vec_index as usize
+        grid_index(self.midpoint_pixel_coords(grid_size), grid_size)
     }
 
     pub fn midpoint_pixel_coords(&self, grid_size: f32) -> Vector2 {
@@ -154,12 +128,18 @@ impl BinaryNode {
         Vector2::new(mid_point[0], mid_point[1])
     }
 }
+/// Index of a grid point in a row major `grid_size` * `grid_size` buffer. Done in f64, past
+/// 4097 the products no longer fit in an f32 mantissa.
+pub fn grid_index(point: Vector2, grid_size: f32) -> usize {
+    (point[1] as f64 * grid_size as f64 + point[0] as f64) as usize
+}
+
 mod tests {
     #![allow(unused)]
     use bevy::prelude::Triangle2d;
     use bevy_tnua::math::Vector2;
 
-    use super::{msb, BinaryNode};
+    use super::{grid_index, msb, BinaryNode};
 
     #[test]
     fn errors_vec_idx_correct() {
@@ -189,20 +169,22 @@ mod tests {
 
     #[test]
     fn msb_works() {
-        let val = 0b1010u32;
+        let val = 0b1010u64;
         assert_eq!(4, msb(val));
 
-        let val = 0b0110u32;
+        let val = 0b0110u64;
         assert_eq!(3, msb(val));
 
-        let val = 0b10110u32;
+        let val = 0b10110u64;
         assert_eq!(5, msb(val));
 
-        let val = 0b100110u32;
+        let val = 0b100110u64;
         assert_eq!(6, msb(val));
 
-        let val = 2u32;
+        let val = 2u64;
         assert_eq!(2, msb(val));
+
+        assert_eq!(64, msb(u64::MAX));
     }
 
     #[test]
@@ -269,7 +251,67 @@ mod tests {
     fn steps_work() {
         let node = BinaryNode::from(6);
         let expected = vec![false, true];
-        assert_eq!(node.steps_to_node(), expected);
+        assert_eq!(node.steps().collect::<Vec<_>>(), expected);
+
+        let node = BinaryNode::from(0b1_0110);
+        let expected = vec![true, false, false, true];
+        assert_eq!(node.steps().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn triangle_indices_correct_for_large_grids() {
+        // 8193 x 8193 grid, every triangle of every level including the unit leaves
+        let side = 8192u64;
+        let number_of_triangles = side * side * 4 - 2;
+        for idx in [
+            side * side - 3,
+            side * side - 2,
+            side * side * 2 - 3,
+            number_of_triangles - 1,
+            1 << 40,
+        ] {
+            let node = BinaryNode::from_triangle_index(idx);
+            assert_eq!(node.triangle_index(), idx);
+        }
+
+        // the last triangle of the hypotenuse 2 level is on the 26th level
+        let node = BinaryNode::from_triangle_index(side * side * 2 - 3);
+        assert_eq!(node.level(), 26);
+        let node = BinaryNode::from_triangle_index(number_of_triangles - 1);
+        assert_eq!(node.level(), 27);
+    }
+
+    #[test]
+    fn triangle_coords_correct_for_large_grids() {
+        let grid_size = 8193.;
+        let side = 8192u64;
+
+        let root = BinaryNode::from(3);
+        let (_, right) = root.children_ids();
+        assert_eq!(right.midpoint_pixel_coords(grid_size), Vector2::new(8192., 4096.));
+        assert_eq!(right.errors_vec_index(grid_size), 4096 * 8193 + 8192);
+
+        for idx in [side * side - 2, side * side + 12345, side * side * 2 - 3] {
+            let node = BinaryNode::from_triangle_index(idx);
+            let [a, b, c] = node.triangle_coords(grid_size).vertices;
+            for v in [a, b, c] {
+                assert_eq!(v, v.round());
+                assert!(v.min_element() >= 0. && v.max_element() <= 8192.);
+            }
+            // finest triangles with an error have a hypotenuse of 2 along an axis
+            assert_eq!((a - b).abs().max_element(), 2.);
+            assert_eq!((a - b).abs().min_element(), 0.);
+            let mid = (a + b) / 2.;
+            assert_eq!(
+                node.errors_vec_index(grid_size),
+                mid.y as usize * 8193 + mid.x as usize
+            );
+            assert!(node.errors_vec_index(grid_size) < 8193 * 8193);
+        }
+
+        // index math is done in f64, f32 would round this one
+        let odd = Vector2::new(8191., 8191.);
+        assert_eq!(grid_index(odd, grid_size), 8191 * 8193 + 8191);
     }
 
     #[test]
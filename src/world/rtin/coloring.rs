@@ -0,0 +1,187 @@
+use bevy::{
+    color::{palettes::css::*, ColorToComponents, LinearRgba},
+    math::Vec3,
+    prelude::Mesh,
+    render::{
+        mesh::{MeshVertexAttribute, VertexAttributeValues},
+        render_resource::VertexFormat,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Per vertex weights of the four splat layers, in the order of `TerrainColoring::layers`.
+/// The weights of a vertex always add up to 1.
+pub const ATTRIBUTE_SPLAT_WEIGHTS: MeshVertexAttribute =
+    MeshVertexAttribute::new("Terrain_SplatWeights", 723_511_048, VertexFormat::Float32x4);
+
+/// A splat layer covers the vertices inside both its height band and its slope band
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SplatLayer {
+    pub color: LinearRgba,
+    /// Mesh space heights, either end may be infinite
+    pub height: Range<f32>,
+    /// Angle between the normal and straight up in radians, either end may be infinite
+    pub slope: Range<f32>,
+}
+
+/// Rule set turning height and slope into vertex colors and splat weights. Band edges are
+/// smoothed over `height_blend` and `slope_blend` so neighbouring layers fade into each other.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TerrainColoring {
+    /// Grass, dirt, rock and snow by default
+    pub layers: [SplatLayer; 4],
+    pub height_blend: f32,
+    pub slope_blend: f32,
+}
+
+impl Default for TerrainColoring {
+    fn default() -> Self {
+        Self {
+            layers: [
+                SplatLayer {
+                    color: FOREST_GREEN.into(),
+                    height: f32::NEG_INFINITY..25.,
+                    slope: f32::NEG_INFINITY..0.45,
+                },
+                SplatLayer {
+                    color: SADDLE_BROWN.into(),
+                    height: f32::NEG_INFINITY..25.,
+                    slope: 0.45..0.8,
+                },
+                SplatLayer {
+                    color: DIM_GRAY.into(),
+                    height: f32::NEG_INFINITY..f32::INFINITY,
+                    slope: 0.8..f32::INFINITY,
+                },
+                SplatLayer {
+                    color: SNOW.into(),
+                    height: 25.0..f32::INFINITY,
+                    slope: f32::NEG_INFINITY..0.8,
+                },
+            ],
+            height_blend: 6.,
+            slope_blend: 0.15,
+        }
+    }
+}
+
+impl TerrainColoring {
+    /// Splat weights of a single vertex. Vertices no band covers fall back to the first layer.
+    pub fn weights(&self, height: f32, normal: Vec3) -> [f32; 4] {
+        let slope = normal.angle_between(Vec3::Y);
+        let mut weights = self.layers.each_ref().map(|layer| {
+            band(height, &layer.height, self.height_blend)
+                * band(slope, &layer.slope, self.slope_blend)
+        });
+
+        let total: f32 = weights.iter().sum();
+        if total <= f32::EPSILON {
+            return [1., 0., 0., 0.];
+        }
+        weights.iter_mut().for_each(|w| *w /= total);
+        weights
+    }
+
+    pub fn color(&self, weights: [f32; 4]) -> LinearRgba {
+        self.layers
+            .iter()
+            .zip(weights)
+            .fold(LinearRgba::NONE, |acc, (layer, w)| acc + layer.color * w)
+    }
+
+    /// Writes `ATTRIBUTE_COLOR` and `ATTRIBUTE_SPLAT_WEIGHTS` from the mesh's positions and
+    /// normals. Meshes without normals, like wireframes, are left untouched.
+    pub fn apply(&self, mesh: &mut Mesh) {
+        let (
+            Some(VertexAttributeValues::Float32x3(positions)),
+            Some(VertexAttributeValues::Float32x3(normals)),
+        ) = (
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+        )
+        else {
+            return;
+        };
+
+        let (colors, weights): (Vec<[f32; 4]>, Vec<[f32; 4]>) = positions
+            .iter()
+            .zip(normals)
+            .map(|(position, normal)| {
+                let weights = self.weights(position[1], Vec3::from_array(*normal));
+                (self.color(weights).to_f32_array(), weights)
+            })
+            .unzip();
+
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+        mesh.insert_attribute(ATTRIBUTE_SPLAT_WEIGHTS, weights);
+    }
+}
+
+/// 1 inside `range`, 0 outside of it, with a smooth ramp `blend` wide centered on each end
+fn band(value: f32, range: &Range<f32>, blend: f32) -> f32 {
+    let ramp = |edge: f32| {
+        if !edge.is_finite() {
+            return if edge < 0. { 1. } else { 0. };
+        }
+        let t = ((value - edge) / blend.max(f32::EPSILON) + 0.5).clamp(0., 1.);
+        t * t * (3. - 2. * t)
+    };
+    ramp(range.start) * (1. - ramp(range.end))
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::{band, TerrainColoring, ATTRIBUTE_SPLAT_WEIGHTS};
+    use crate::world::rtin::{build_terrain_from_sampler, PlaneSampler};
+    use bevy::{math::Vec3, prelude::Mesh, render::mesh::VertexAttributeValues};
+
+    #[test]
+    fn band_ramps() {
+        assert_eq!(band(5., &(0.0..10.0), 2.), 1.);
+        assert_eq!(band(-5., &(0.0..10.0), 2.), 0.);
+        assert_eq!(band(0., &(0.0..10.0), 2.), 0.5);
+        assert_eq!(band(10., &(0.0..10.0), 2.), 0.5);
+        assert_eq!(band(-1000., &(f32::NEG_INFINITY..10.0), 2.), 1.);
+        assert_eq!(band(1000., &(0.0..f32::INFINITY), 2.), 1.);
+    }
+
+    #[test]
+    fn weights_follow_rules() {
+        let coloring = TerrainColoring::default();
+        assert_eq!(coloring.weights(0., Vec3::Y), [1., 0., 0., 0.]);
+        assert_eq!(coloring.weights(100., Vec3::Y), [0., 0., 0., 1.]);
+        let cliff = Vec3::new(1., 0.1, 0.).normalize();
+        assert_eq!(coloring.weights(0., cliff), [0., 0., 1., 0.]);
+
+        let between = coloring.weights(25., Vec3::Y);
+        assert!((between.iter().sum::<f32>() - 1.).abs() < 1e-6);
+        assert!(between[0] > 0. && between[3] > 0.);
+    }
+
+    struct Ramp;
+
+    impl PlaneSampler for Ramp {
+        fn get(&self, x: f32, _y: f32) -> f32 {
+            x
+        }
+    }
+
+    #[test]
+    fn apply_writes_attributes() {
+        let terrain = build_terrain_from_sampler(&Ramp, 2., 16., 0.);
+        let mut mesh = terrain.into_mesh(false, 16.);
+        TerrainColoring::default().apply(&mut mesh);
+
+        let Some(VertexAttributeValues::Float32x4(weights)) =
+            mesh.attribute(ATTRIBUTE_SPLAT_WEIGHTS)
+        else {
+            panic!("splat weights missing");
+        };
+        assert_eq!(weights.len(), terrain.vertices.len());
+        assert!(weights
+            .iter()
+            .all(|w| (w.iter().sum::<f32>() - 1.).abs() < 1e-5));
+        assert!(mesh.attribute(Mesh::ATTRIBUTE_COLOR).is_some());
+    }
+}
@@ -0,0 +1,345 @@
+use super::TerrainMeshData;
+use bevy::math::Vec3;
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Which optional vertex attributes get written. UVs are generated the same way as in
+/// `TerrainMeshData::into_mesh`, by dividing x and z by `uv_size`.
+#[derive(Debug, Clone, Copy)]
+pub struct ExportOptions {
+    pub normals: bool,
+    pub uvs: bool,
+    pub uv_size: f32,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            normals: true,
+            uvs: true,
+            uv_size: 1.,
+        }
+    }
+}
+
+const GLTF_FLOAT: u32 = 5126;
+const GLTF_UNSIGNED_INT: u32 = 5125;
+const GLTF_ARRAY_BUFFER: u32 = 34962;
+const GLTF_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+impl TerrainMeshData {
+    /// Face normal of a triangle following the winding used for rendering
+    pub fn face_normal(&self, triangle: usize) -> Vec3 {
+        let [a, b, c] = self.triangle(triangle);
+        (b - a).cross(c - a).normalize_or_zero()
+    }
+
+    fn triangle(&self, triangle: usize) -> [Vec3; 3] {
+        let i = triangle * 3;
+        [
+            self.vertices[self.indices[i] as usize],
+            self.vertices[self.indices[i + 1] as usize],
+            self.vertices[self.indices[i + 2] as usize],
+        ]
+    }
+
+    /// Smooth normals, every face contributes to its vertices weighted by its area
+    pub fn vertex_normals(&self) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::ZERO; self.vertices.len()];
+        for tri in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.vertices[tri[i] as usize]);
+            let weighted = (b - a).cross(c - a);
+            for idx in tri {
+                normals[*idx as usize] += weighted;
+            }
+        }
+        normals.iter().map(|n| n.normalize_or_zero()).collect()
+    }
+
+    /// The sampled normals when there is one per vertex, smooth normals otherwise
+    fn export_normals(&self) -> Cow<'_, [Vec3]> {
+        if self.normals.len() == self.vertices.len() {
+            Cow::Borrowed(&self.normals)
+        } else {
+            Cow::Owned(self.vertex_normals())
+        }
+    }
+
+    pub fn uvs(&self, uv_size: f32) -> Vec<[f32; 2]> {
+        self.vertices
+            .iter()
+            .map(|v| [v.x / uv_size, v.z / uv_size])
+            .collect()
+    }
+
+    /// Picks the writer from the extension of `path`: obj, stl or gltf
+    pub fn export(&self, path: impl AsRef<Path>, options: ExportOptions) -> io::Result<()> {
+        let path = path.as_ref();
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match ext.as_str() {
+            "obj" => self.write_obj(&mut BufWriter::new(File::create(path)?), options),
+            "stl" => self.write_stl(&mut BufWriter::new(File::create(path)?)),
+            "gltf" => self.write_gltf(path, options),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported terrain export extension: {other}"),
+            )),
+        }
+    }
+
+    pub fn write_obj(&self, w: &mut impl Write, options: ExportOptions) -> io::Result<()> {
+        writeln!(w, "o terrain")?;
+        for v in &self.vertices {
+            writeln!(w, "v {} {} {}", v.x, v.y, v.z)?;
+        }
+        if options.uvs {
+            for uv in self.uvs(options.uv_size) {
+                writeln!(w, "vt {} {}", uv[0], uv[1])?;
+            }
+        }
+        if options.normals {
+            for n in self.export_normals().iter() {
+                writeln!(w, "vn {} {} {}", n.x, n.y, n.z)?;
+            }
+        }
+
+        for tri in self.indices.chunks_exact(3) {
+            write!(w, "f")?;
+            for idx in tri {
+                // obj indices start at 1
+                let i = idx + 1;
+                match (options.uvs, options.normals) {
+                    (true, true) => write!(w, " {i}/{i}/{i}")?,
+                    (true, false) => write!(w, " {i}/{i}")?,
+                    (false, true) => write!(w, " {i}//{i}")?,
+                    (false, false) => write!(w, " {i}")?,
+                }
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// Binary STL, which only stores per face normals
+    pub fn write_stl(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut header = [0u8; 80];
+        let name = b"prototype_slenderish terrain";
+        header[..name.len()].copy_from_slice(name);
+        w.write_all(&header)?;
+
+        let triangle_count = self.indices.len() / 3;
+        w.write_all(&(triangle_count as u32).to_le_bytes())?;
+
+        for triangle in 0..triangle_count {
+            let normal = self.face_normal(triangle);
+            for v in std::iter::once(normal).chain(self.triangle(triangle)) {
+                for c in v.to_array() {
+                    w.write_all(&c.to_le_bytes())?;
+                }
+            }
+            // attribute byte count
+            w.write_all(&0u16.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Writes `path` and a `.bin` buffer with the same file stem next to it
+    pub fn write_gltf(&self, path: impl AsRef<Path>, options: ExportOptions) -> io::Result<()> {
+        let path = path.as_ref();
+        let bin_path = path.with_extension("bin");
+        let bin_name = bin_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("terrain.bin")
+            .to_string();
+
+        let mut buffer = Vec::<u8>::new();
+        let mut buffer_views = Vec::<String>::new();
+        let mut accessors = Vec::<String>::new();
+
+        let mut push_view = |buffer: &mut Vec<u8>, bytes: Vec<u8>, target: u32| {
+            let view = format!(
+                r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{target}}}"#,
+                buffer.len(),
+                bytes.len()
+            );
+            buffer.extend(bytes);
+            buffer_views.push(view);
+            buffer_views.len() - 1
+        };
+
+        let index_bytes = self.indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let view = push_view(&mut buffer, index_bytes, GLTF_ELEMENT_ARRAY_BUFFER);
+        accessors.push(format!(
+            r#"{{"bufferView":{view},"componentType":{GLTF_UNSIGNED_INT},"count":{},"type":"SCALAR"}}"#,
+            self.indices.len()
+        ));
+        let mut attributes = Vec::<String>::new();
+
+        let (min, max) = self.vertices.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), v| (min.min(*v), max.max(*v)),
+        );
+        let position_bytes = floats_to_bytes(self.vertices.iter().flat_map(|v| v.to_array()));
+        let view = push_view(&mut buffer, position_bytes, GLTF_ARRAY_BUFFER);
+        accessors.push(format!(
+            r#"{{"bufferView":{view},"componentType":{GLTF_FLOAT},"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+            self.vertices.len(),
+            min.x, min.y, min.z, max.x, max.y, max.z
+        ));
+        attributes.push(format!(r#""POSITION":{}"#, accessors.len() - 1));
+
+        if options.normals {
+            let normals = self.export_normals();
+            let normal_bytes = floats_to_bytes(normals.iter().flat_map(|n| n.to_array()));
+            let view = push_view(&mut buffer, normal_bytes, GLTF_ARRAY_BUFFER);
+            accessors.push(format!(
+                r#"{{"bufferView":{view},"componentType":{GLTF_FLOAT},"count":{},"type":"VEC3"}}"#,
+                normals.len()
+            ));
+            attributes.push(format!(r#""NORMAL":{}"#, accessors.len() - 1));
+        }
+
+        if options.uvs {
+            let uvs = self.uvs(options.uv_size);
+            let uv_bytes = floats_to_bytes(uvs.iter().flatten().copied());
+            let view = push_view(&mut buffer, uv_bytes, GLTF_ARRAY_BUFFER);
+            accessors.push(format!(
+                r#"{{"bufferView":{view},"componentType":{GLTF_FLOAT},"count":{},"type":"VEC2"}}"#,
+                uvs.len()
+            ));
+            attributes.push(format!(r#""TEXCOORD_0":{}"#, accessors.len() - 1));
+        }
+
+        let json = format!(
+            r#"{{
+  "asset": {{"version": "2.0", "generator": "prototype_slenderish"}},
+  "scene": 0,
+  "scenes": [{{"nodes": [0]}}],
+  "nodes": [{{"mesh": 0, "name": "terrain"}}],
+  "meshes": [{{"primitives": [{{"attributes": {{{}}}, "indices": 0, "mode": 4}}]}}],
+  "buffers": [{{"uri": "{bin_name}", "byteLength": {}}}],
+  "bufferViews": [{}],
+  "accessors": [{}]
+}}
+"#,
+            attributes.join(","),
+            buffer.len(),
+            buffer_views.join(","),
+            accessors.join(",")
+        );
+
+        std::fs::write(&bin_path, &buffer)?;
+        std::fs::write(path, json)
+    }
+}
+
+fn floats_to_bytes(floats: impl Iterator<Item = f32>) -> Vec<u8> {
+    floats.flat_map(|f| f.to_le_bytes()).collect()
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::ExportOptions;
+    use crate::world::rtin::TerrainMeshData;
+    use bevy::math::Vec3;
+
+    fn quad() -> TerrainMeshData {
+        TerrainMeshData {
+            vertices: vec![
+                Vec3::new(1., 0., 1.),
+                Vec3::new(0., 0., 0.),
+                Vec3::new(0., 0., 1.),
+                Vec3::new(1., 0., 0.),
+            ],
+            indices: vec![0, 1, 2, 1, 0, 3],
+            normals: vec![],
+            stats: None,
+        }
+    }
+
+    #[test]
+    fn normals_point_up() {
+        let quad = quad();
+        assert_eq!(quad.face_normal(0), Vec3::Y);
+        assert_eq!(quad.face_normal(1), Vec3::Y);
+        assert!(quad.vertex_normals().iter().all(|n| *n == Vec3::Y));
+    }
+
+    #[test]
+    fn obj_written() {
+        let mut out = Vec::new();
+        let options = ExportOptions {
+            normals: false,
+            uvs: true,
+            uv_size: 2.,
+        };
+        quad().write_obj(&mut out, options).unwrap();
+        let obj = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = obj.lines().collect();
+        assert_eq!(lines[1], "v 1 0 1");
+        assert_eq!(lines[5], "vt 0.5 0.5");
+        assert_eq!(lines[9], "f 1/1 2/2 3/3");
+        assert_eq!(lines[10], "f 2/2 1/1 4/4");
+        assert_eq!(lines.len(), 11);
+    }
+
+    #[test]
+    fn sampled_normals_written() {
+        let tilted = Vec3::new(1., 1., 0.).normalize();
+        let quad = TerrainMeshData {
+            normals: vec![tilted; 4],
+            ..quad()
+        };
+        let mut out = Vec::new();
+        quad.write_obj(&mut out, ExportOptions::default()).unwrap();
+        let obj = String::from_utf8(out).unwrap();
+        let expected = format!("vn {} {} {}", tilted.x, tilted.y, tilted.z);
+        assert_eq!(obj.lines().filter(|l| *l == expected).count(), 4);
+
+        let dir = std::env::temp_dir().join("prototype_slenderish_gltf_normals");
+        std::fs::create_dir_all(&dir).unwrap();
+        quad.export(dir.join("quad.gltf"), ExportOptions::default())
+            .unwrap();
+        let bin = std::fs::read(dir.join("quad.bin")).unwrap();
+        // normals follow the indices and positions
+        let start = 6 * 4 + 4 * 12;
+        let x = f32::from_le_bytes(bin[start..start + 4].try_into().unwrap());
+        assert_eq!(x, tilted.x);
+    }
+
+    #[test]
+    fn stl_written() {
+        let mut out = Vec::new();
+        quad().write_stl(&mut out).unwrap();
+        assert_eq!(out.len(), 84 + 2 * 50);
+        assert_eq!(u32::from_le_bytes([out[80], out[81], out[82], out[83]]), 2);
+        // first facet normal y component
+        assert_eq!(f32::from_le_bytes([out[88], out[89], out[90], out[91]]), 1.);
+    }
+
+    #[test]
+    fn gltf_written() {
+        let dir = std::env::temp_dir().join("prototype_slenderish_gltf_export");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("quad.gltf");
+        quad().export(&path, ExportOptions::default()).unwrap();
+
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(json.contains(r#""uri": "quad.bin""#));
+        assert!(json.contains(r#""POSITION":1,"NORMAL":2,"TEXCOORD_0":3"#));
+
+        let bin = std::fs::read(dir.join("quad.bin")).unwrap();
+        // indices, positions, normals, uvs
+        assert_eq!(bin.len(), 6 * 4 + 4 * 12 + 4 * 12 + 4 * 8);
+        assert!(json.contains(&format!(r#""byteLength": {}"#, bin.len())));
+    }
+}
@@ -1,27 +1,90 @@
 pub mod binary_node;
+pub mod coloring;
+pub mod export;
+pub mod skirts;
+pub mod stats;
+use super::heightmap::HeightGrid;
 use bevy::{
-    log::warn,
-    math::Vec3,
+    math::{Vec2, Vec3},
     prelude::Mesh,
     render::{
         mesh::{Indices, PrimitiveTopology},
         render_asset::RenderAssetUsages,
     },
+    tasks::{ComputeTaskPool, TaskPool},
+    utils::Instant,
 };
 use bevy_tnua::math::Vector2;
 use binary_node::*;
-use std::{collections::HashMap, u32};
+use stats::{ErrorStats, PhaseTimings, TerrainStats};
+use std::{
+    collections::{BinaryHeap, HashMap},
+    fmt::Display,
+    sync::Arc,
+    u32,
+};
 
 #[derive(Debug)]
 pub struct TerrainMeshData {
     pub vertices: Vec<Vec3>,
     pub indices: Vec<u32>,
+    /// One per vertex when built from a sampler, empty if the normals should come from the
+    /// triangles instead
+    pub normals: Vec<Vec3>,
+    /// Set by the RTIN builders, `None` for meshes made any other way
+    pub stats: Option<TerrainStats>,
 }
 
-pub trait PlaneSampler {
+/// Samplers are shared between systems and chunk builds, so they must be thread safe
+pub trait PlaneSampler: Send + Sync {
     fn get(&self, x: f32, y: f32) -> f32;
 }
 
+impl<T: PlaneSampler + ?Sized> PlaneSampler for &T {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        (**self).get(x, y)
+    }
+}
+
+impl<T: PlaneSampler + ?Sized> PlaneSampler for Arc<T> {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        (**self).get(x, y)
+    }
+}
+
+/// Samples the inner sampler shifted by `offset`, so a chunk built in local grid coordinates
+/// reads the same heights as the world position it is placed at
+#[derive(Debug, Clone)]
+pub struct OffsetSampler<S> {
+    pub sampler: S,
+    pub offset: Vec2,
+}
+
+impl<S: PlaneSampler> OffsetSampler<S> {
+    pub fn new(sampler: S, offset: Vec2) -> Self {
+        Self { sampler, offset }
+    }
+}
+
+impl<S: PlaneSampler> PlaneSampler for OffsetSampler<S> {
+    fn get(&self, x: f32, y: f32) -> f32 {
+        self.sampler.get(x + self.offset.x, y + self.offset.y)
+    }
+}
+
+/// Distance between the samples taken on either side of a point by `sampler_normal`. One grid
+/// cell, so normals at grid vertices only read heights the mesh build already sampled.
+pub const NORMAL_SAMPLE_STEP: f32 = 1.;
+
+/// Surface normal of the sampled terrain at `(x, y)`, from central differences of the scaled
+/// heights. The sampler's y axis becomes world z.
+pub fn sampler_normal(sampler: &impl PlaneSampler, x: f32, y: f32, height_multiplier: f32) -> Vec3 {
+    let step = NORMAL_SAMPLE_STEP;
+    let dx = (sampler.get(x + step, y) - sampler.get(x - step, y)) * height_multiplier;
+    let dz = (sampler.get(x, y + step) - sampler.get(x, y - step)) * height_multiplier;
+    Vec3::new(-dx, 2. * step, -dz).normalize()
+}
+
 impl TerrainMeshData {
     pub fn into_mesh(&self, enable_wireframe: bool, size: f32) -> Mesh {
         let topology = if enable_wireframe {
@@ -41,15 +104,9 @@ impl TerrainMeshData {
         let mut vertices: Vec<[f32; 3]> = Vec::new();
         let mut uvs = Vec::new();
         let mut indices: Vec<u32> = Vec::new();
-        let mut colors: Vec<[f32; 3]> = Vec::new();
 
         vertices.reserve(self.vertices.len());
-        colors.reserve(vertices.len());
         indices.reserve(indices_len);
-        // let grad = palette::Mic Mix::new(vec![
-        //     Hsv::from(),
-        //     Hsv::from(),
-        // ]);
 
         for vertex in &self.vertices {
             vertices.push([vertex.x, vertex.y, vertex.z]);
@@ -74,12 +131,15 @@ impl TerrainMeshData {
 
         mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
         mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-        // mesh.insert_attribute(Material, values)
-        // mesh.insert_attribute(StandardMaterial::A, colors);
         mesh.insert_indices(Indices::U32(indices));
 
         if !enable_wireframe {
-            mesh.compute_normals();
+            if self.normals.len() == self.vertices.len() {
+                let normals: Vec<[f32; 3]> = self.normals.iter().map(|n| n.to_array()).collect();
+                mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+            } else {
+                mesh.compute_normals();
+            }
         }
 
         mesh
@@ -92,21 +152,222 @@ pub fn build_terrain_from_sampler(
     size: f32,
     error_threshold: f32,
 ) -> TerrainMeshData {
-    let grid_size = size + 1.;
-    let errors = get_errors_vec(sampler, grid_size);
-    // warn!("got errors vec: \n{errors:?}");
-    let avg = errors.iter().fold(0., |acc, e| acc + e) / errors.len() as f32;
-    warn!("avg error: {}", avg);
+    let refinement = Refinement::ErrorThreshold(error_threshold);
+    build_terrain(sampler, height_multiplier, size, refinement, |_| false)
+}
+
+/// Same as `build_terrain_from_sampler`, but refined until the mesh has as many triangles as
+/// fits in `max_triangles` instead of down to an error threshold
+pub fn build_terrain_with_budget(
+    sampler: &impl PlaneSampler,
+    height_multiplier: f32,
+    size: f32,
+    max_triangles: usize,
+) -> TerrainMeshData {
+    let refinement = Refinement::MaxTriangles(max_triangles);
+    build_terrain(sampler, height_multiplier, size, refinement, |_| false)
+}
+
+/// Same as `build_terrain_from_sampler`, but every grid vertex along the four borders is kept.
+/// Two chunks built this way always share identical edge vertices whatever their
+/// `error_threshold`, so chunks at different LODs can sit next to each other without cracks.
+pub fn build_chunk_terrain_from_sampler(
+    sampler: &impl PlaneSampler,
+    height_multiplier: f32,
+    size: f32,
+    error_threshold: f32,
+) -> TerrainMeshData {
+    let on_edge = |p: Vector2| p[0] == 0. || p[1] == 0. || p[0] == size || p[1] == size;
+    let refinement = Refinement::ErrorThreshold(error_threshold);
+    build_terrain(sampler, height_multiplier, size, refinement, on_edge)
+}
+
+/// Chunk counterpart of `build_terrain_with_budget`. The border vertices are always kept, so
+/// a budget too small for them is exceeded rather than leaving cracks between chunks.
+pub fn build_chunk_terrain_with_budget(
+    sampler: &impl PlaneSampler,
+    height_multiplier: f32,
+    size: f32,
+    max_triangles: usize,
+) -> TerrainMeshData {
+    let on_edge = |p: Vector2| p[0] == 0. || p[1] == 0. || p[0] == size || p[1] == size;
+    let refinement = Refinement::MaxTriangles(max_triangles);
+    build_terrain(sampler, height_multiplier, size, refinement, on_edge)
+}
+
+/// Builds terrain covering any integer `width` by `depth` of at least 1, other extents are
+/// rejected. The area is tiled with square RTIN blocks, sized to the smallest power of 2
+/// covering the shorter side. Grid lines where blocks meet are locked so neighbouring blocks
+/// share their vertices, and so are the lines where the extent cuts through a block, which
+/// keeps every triangle on one side of the cut. Triangles past the extent are then dropped. The
+/// stats add up the blocks, so apart from the mesh counts they still include the dropped
+/// triangles.
+pub fn build_terrain_rect_from_sampler(
+    sampler: &impl PlaneSampler,
+    height_multiplier: f32,
+    width: f32,
+    depth: f32,
+    error_threshold: f32,
+) -> Result<TerrainMeshData, InvalidExtent> {
+    InvalidExtent::check(width, depth)?;
+    let block_size = (width.min(depth) as u32).next_power_of_two().max(2) as f32;
+    let blocks_x = (width / block_size).ceil() as u32;
+    let blocks_z = (depth / block_size).ceil() as u32;
 
     let mut vertices = Vec::<Vec3>::new();
     let mut indices = Vec::<u32>::new();
-    let mut vertices_array_position = HashMap::<u32, usize>::new();
+    let mut normals = Vec::<Vec3>::new();
+    let mut vertices_array_position = HashMap::<(u32, u32), u32>::new();
+    let mut stats: Option<TerrainStats> = None;
+
+    for bz in 0..blocks_z {
+        for bx in 0..blocks_x {
+            let origin = Vec2::new(bx as f32, bz as f32) * block_size;
+            // extent in the block's local coordinates, may reach past the block
+            let clip = Vec2::new(width, depth) - origin;
+
+            let lock = |p: Vector2| {
+                (bx > 0 && p[0] == 0.)
+                    || (bz > 0 && p[1] == 0.)
+                    || (bx < blocks_x - 1 && p[0] == block_size)
+                    || (bz < blocks_z - 1 && p[1] == block_size)
+                    || (clip.x < block_size && p[0] == clip.x)
+                    || (clip.y < block_size && p[1] == clip.y)
+            };
+            let block = build_terrain(
+                &OffsetSampler::new(sampler, origin),
+                height_multiplier,
+                block_size,
+                Refinement::ErrorThreshold(error_threshold),
+                lock,
+            );
+            let block_stats = block.stats.unwrap();
+            stats = Some(match stats {
+                Some(stats) => stats.merge(&block_stats),
+                None => block_stats,
+            });
+
+            for tri in block.indices.chunks_exact(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| block.vertices[tri[i] as usize]);
+                let centroid = (a + b + c) / 3.;
+                if centroid.x > clip.x || centroid.z > clip.y {
+                    continue;
+                }
 
+                for (i, v) in tri.iter().zip([a, b, c]) {
+                    let key = ((v.x + origin.x) as u32, (v.z + origin.y) as u32);
+                    let idx = *vertices_array_position.entry(key).or_insert_with(|| {
+                        vertices.push(Vec3::new(v.x + origin.x, v.y, v.z + origin.y));
+                        normals.push(block.normals[*i as usize]);
+                        vertices.len() as u32 - 1
+                    });
+                    indices.push(idx);
+                }
+            }
+        }
+    }
+
+    let stats = stats.map(|stats| TerrainStats {
+        vertices: vertices.len(),
+        triangles: indices.len() / 3,
+        ..stats
+    });
+    Ok(TerrainMeshData {
+        vertices,
+        indices,
+        normals,
+        stats,
+    })
+}
+
+/// A terrain extent that isn't a whole number of grid cells, or is less than one cell
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidExtent {
+    pub width: f32,
+    pub depth: f32,
+}
+
+impl InvalidExtent {
+    pub fn check(width: f32, depth: f32) -> Result<(), Self> {
+        if width >= 1. && depth >= 1. && width.fract() == 0. && depth.fract() == 0. {
+            Ok(())
+        } else {
+            Err(Self { width, depth })
+        }
+    }
+}
+
+impl Display for InvalidExtent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "terrain extent must be whole grid cells, got {} x {}",
+            self.width, self.depth
+        )
+    }
+}
+
+impl std::error::Error for InvalidExtent {}
+
+/// How far `build_terrain` refines the mesh
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Refinement {
+    ErrorThreshold(f32),
+    /// See `budget_error_threshold`
+    MaxTriangles(usize),
+}
+
+/// `lock` is checked against every triangle's hypotenuse midpoint, see `get_errors_vec`
+fn build_terrain(
+    sampler: &impl PlaneSampler,
+    height_multiplier: f32,
+    size: f32,
+    refinement: Refinement,
+    lock: impl Fn(Vector2) -> bool,
+) -> TerrainMeshData {
+    let grid_size = size + 1.;
+    let mut timings = PhaseTimings::default();
+    let start = Instant::now();
+    let heights = sample_height_grid(sampler, grid_size);
+    let ring = BorderRing::sample(sampler, grid_size);
+    timings.sampling = start.elapsed();
+
+    let start = Instant::now();
+    let errors = get_errors_vec(&heights, lock);
+    timings.errors = start.elapsed();
+
+    let mut vertices = Vec::<Vec3>::new();
+    let mut indices = Vec::<u32>::new();
+    let mut normals = Vec::<Vec3>::new();
+    let mut vertices_array_position = HashMap::<usize, usize>::new();
+
+    let start = Instant::now();
+    let error_threshold = match refinement {
+        Refinement::ErrorThreshold(error_threshold) => error_threshold,
+        Refinement::MaxTriangles(max_triangles) => {
+            budget_error_threshold(size, &errors, max_triangles)
+        }
+    };
     let nodes = select_nodes(size, &errors, error_threshold);
-    // debug!("building terrain from nodes: {nodes:?}");
+    timings.selection = start.elapsed();
 
+    let start = Instant::now();
+    let mut nodes_per_level = vec![];
+    let mut kept_errors = Vec::with_capacity(nodes.len());
     for node in nodes {
-        let triangle_coords = node.triangle_coords(size);
+        let triangle_coords = node.triangle_coords(grid_size);
+        let level = node.level() as usize - 1;
+        if nodes_per_level.len() <= level {
+            nodes_per_level.resize(level + 1, 0);
+        }
+        nodes_per_level[level] += 1;
+        // unit triangles have their hypotenuse midpoint off the grid and no error
+        let midpoint = (triangle_coords.vertices[0] + triangle_coords.vertices[1]) / 2.;
+        kept_errors.push(if midpoint.fract() == Vector2::ZERO {
+            errors[grid_index(midpoint, grid_size)]
+        } else {
+            0.
+        });
         let new_vertices = &[
             &triangle_coords.vertices[0],
             &triangle_coords.vertices[1],
@@ -114,7 +375,7 @@ pub fn build_terrain_from_sampler(
         ];
 
         for new_vertex in new_vertices {
-            let vertex_id = (new_vertex[1] * size + new_vertex[0]) as u32;
+            let vertex_id = grid_index(**new_vertex, grid_size);
 
             let vertex_index = match vertices_array_position.get(&vertex_id) {
                 Some(i) => i.to_owned(),
@@ -122,20 +383,90 @@ pub fn build_terrain_from_sampler(
                     let new_vertex_index = vertices.len();
                     vertices_array_position.insert(vertex_id, new_vertex_index);
 
-                    let vertex_height =
-                        sample_corner_mean(sampler, &size, **new_vertex) * height_multiplier;
+                    let (x, y) = (new_vertex[0] as usize, new_vertex[1] as usize);
+                    let vertex_height = heights.get(x, y) * height_multiplier;
 
                     let new_vertex_3d =
                         Vec3::new(new_vertex[0] as f32, vertex_height, new_vertex[1] as f32);
                     vertices.push(new_vertex_3d);
+                    normals.push(ring.normal(&heights, x, y, height_multiplier));
                     new_vertex_index.to_owned()
                 }
             };
             indices.push(vertex_index as u32);
         }
     }
+    timings.mesh = start.elapsed();
+
+    let (min_height, max_height) = heights
+        .data
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), h| {
+            (min.min(*h), max.max(*h))
+        });
+    let stats = TerrainStats {
+        vertices: vertices.len(),
+        triangles: indices.len() / 3,
+        error_threshold,
+        error_before: ErrorStats::new(errors.iter().copied()),
+        error_after: ErrorStats::new(kept_errors),
+        nodes_per_level,
+        min_height: min_height * height_multiplier,
+        max_height: max_height * height_multiplier,
+        timings,
+    };
+
+    TerrainMeshData {
+        vertices,
+        indices,
+        normals,
+        stats: Some(stats),
+    }
+}
 
-    TerrainMeshData { vertices, indices }
+/// Heights of the lattice points one step outside each border of a height grid, so vertices
+/// on the border get the same central difference normals as `sampler_normal` gives everywhere
+/// else. Neighbouring chunks read the same points, so their normals match along shared edges.
+struct BorderRing {
+    /// Indexed along the border, at x = -1, x = grid size, y = -1 and y = grid size
+    left: Vec<f32>,
+    right: Vec<f32>,
+    bottom: Vec<f32>,
+    top: Vec<f32>,
+}
+
+impl BorderRing {
+    fn sample(sampler: &impl PlaneSampler, grid_size: f32) -> Self {
+        let along = |f: &dyn Fn(f32) -> f32| -> Vec<f32> {
+            (0..grid_size as usize).map(|i| f(i as f32)).collect()
+        };
+        Self {
+            left: along(&|y| sampler.get(-1., y)),
+            right: along(&|y| sampler.get(grid_size, y)),
+            bottom: along(&|x| sampler.get(x, -1.)),
+            top: along(&|x| sampler.get(x, grid_size)),
+        }
+    }
+
+    fn height(&self, heights: &HeightGrid, x: i64, y: i64) -> f32 {
+        let side = heights.width as i64;
+        match (x, y) {
+            (-1, y) => self.left[y as usize],
+            (x, y) if x == side => self.right[y as usize],
+            (x, -1) => self.bottom[x as usize],
+            (x, y) if y == side => self.top[x as usize],
+            (x, y) => heights.get(x as usize, y as usize),
+        }
+    }
+
+    fn normal(&self, heights: &HeightGrid, x: usize, y: usize, height_multiplier: f32) -> Vec3 {
+        let (x, y) = (x as i64, y as i64);
+        let dx =
+            (self.height(heights, x + 1, y) - self.height(heights, x - 1, y)) * height_multiplier;
+        let dz =
+            (self.height(heights, x, y + 1) - self.height(heights, x, y - 1)) * height_multiplier;
+        Vec3::new(-dx, 2. * NORMAL_SAMPLE_STEP, -dz).normalize()
+    }
 }
 
 const fn num_bits<T>() -> usize {
@@ -145,39 +476,72 @@ fn log_2(x: u32) -> u32 {
     num_bits::<u32>() as u32 - x.leading_zeros() - 1
 }
 
-fn get_errors_vec(sampler: &impl PlaneSampler, grid_size: f32) -> Vec<f32> {
-    // let grid_grid_size = size + 1.;
-    let number_of_triangles = grid_size as u32 * grid_size as u32 * 2 - 2;
+/// Evaluates the sampler exactly once for every grid vertex. Rows are split between the
+/// threads of the `ComputeTaskPool`, so the sampler is never called twice for the same point
+/// by the error pass or the vertex emission.
+pub fn sample_height_grid(sampler: &impl PlaneSampler, grid_size: f32) -> HeightGrid {
+    let width = grid_size as usize;
+    let mut data = vec![0.; width * width];
+
+    let pool = ComputeTaskPool::get_or_init(TaskPool::default);
+    let rows_per_task = width.div_ceil(pool.thread_num().max(1)).max(1);
+    pool.scope(|scope| {
+        for (task_idx, rows) in data.chunks_mut(rows_per_task * width).enumerate() {
+            scope.spawn(async move {
+                for (row_idx, row) in rows.chunks_mut(width).enumerate() {
+                    let y = (task_idx * rows_per_task + row_idx) as f32;
+                    for (x, height) in row.iter_mut().enumerate() {
+                        *height = sampler.get(x as f32, y);
+                    }
+                }
+            });
+        }
+    });
+
+    HeightGrid::new(width, width, data)
+}
+
+/// Triangles whose midpoint is `lock`ed get the maximum error, forcing them to always be split
+/// down to the finest level. Locking a grid line therefore keeps every vertex along it.
+fn get_errors_vec(heights: &HeightGrid, lock: impl Fn(Vector2) -> bool) -> Vec<f32> {
+    let grid_size = heights.width as f32;
+    let side = heights.width as u64 - 1;
+    let number_of_triangles = side * side * 2 - 2;
     let number_of_levels = log_2(grid_size as u32) * 2;
     let last_level = number_of_levels - 1;
 
     let last_level_index_start = BinaryNode::level_start_index(last_level);
 
-    let mut errors_vec = Vec::new();
-    errors_vec.resize((grid_size * grid_size) as usize, 0.0f32);
+    let mut errors_vec = vec![0.0f32; heights.width * heights.width];
 
     for idx in (0..number_of_triangles).rev() {
         let node = BinaryNode::from_triangle_index(idx);
 
-        let midpoint = node.midpoint_pixel_coords(grid_size);
+        // walking down to the triangle is the expensive part, so its children's midpoints are
+        // worked out from its vertices instead of from their own ids
+        let [a, b, c] = node.triangle_coords(grid_size).vertices;
+        let midpoint = (a + b) / 2.;
 
-        let triangle_coords = node.triangle_coords(grid_size);
-        let h0 = sample_corner_mean(sampler, &grid_size, triangle_coords.vertices[0]);
-        let h1 = sample_corner_mean(sampler, &grid_size, triangle_coords.vertices[1]);
+        let height_at = |v: Vector2| heights.get(v[0] as usize, v[1] as usize);
+        let h0 = height_at(a);
+        let h1 = height_at(b);
         let midpoint_interpolated = (h1 + h0) / 2.0;
-        let midpoint_height = sample_corner_mean(sampler, &grid_size, midpoint);
+        let midpoint_height = height_at(midpoint);
 
-        let this_triangle_error = (midpoint_interpolated - midpoint_height).abs();
+        let this_triangle_error = if lock(midpoint) {
+            f32::MAX
+        } else {
+            (midpoint_interpolated - midpoint_height).abs()
+        };
 
-        let this_triangle_mid_point_error_vec_index = node.errors_vec_index(grid_size);
+        let this_triangle_mid_point_error_vec_index = grid_index(midpoint, grid_size);
 
         if idx >= last_level_index_start {
             errors_vec[this_triangle_mid_point_error_vec_index] = this_triangle_error;
         } else {
-            let (left_child, right_child) = node.children_ids();
-
-            let right_errors_vec_index = right_child.errors_vec_index(grid_size);
-            let left_errors_vec_index = left_child.errors_vec_index(grid_size);
+            // children are (c, a, midpoint) and (b, c, midpoint)
+            let left_errors_vec_index = grid_index((c + a) / 2., grid_size);
+            let right_errors_vec_index = grid_index((b + c) / 2., grid_size);
 
             let prev_error = errors_vec[this_triangle_mid_point_error_vec_index];
             let right_error = errors_vec[right_errors_vec_index];
@@ -207,92 +571,349 @@ pub fn sample_corner_mean(sampler: &impl PlaneSampler, size: &f32, corner_u32: V
     sampler.get(new_corner[0], new_corner[1])
 }
 
-fn select_nodes(size: f32, errors_vec: &Vec<f32>, error_threshold: f32) -> Vec<BinaryNode> {
+/// Walks the tree depth first, left child before right, keeping every triangle whose error is
+/// within the threshold. Iterative so very large grids can't exhaust the stack.
+fn select_nodes(size: f32, errors_vec: &[f32], error_threshold: f32) -> Vec<BinaryNode> {
+    let grid_size = size + 1.;
+    let side = size as u64;
+    // includes the unit leaves below the finest level that has errors
+    let number_of_last_level_triangles = side * side * 2;
+    let number_of_triangles = side * side * 2 - 2 + number_of_last_level_triangles;
+
     let mut nodes = Vec::<BinaryNode>::new();
+    let mut stack = vec![
+        BinaryNode::from_triangle_index(1),
+        BinaryNode::from_triangle_index(0),
+    ];
+
+    while let Some(node) = stack.pop() {
+        let (left_child, right_child) = node.children_ids();
+        let leaf_node = right_child.triangle_index() >= number_of_triangles;
 
-    select_nodes_for_heightmap(size, errors_vec, &mut nodes, 0, error_threshold);
-    select_nodes_for_heightmap(size, errors_vec, &mut nodes, 1, error_threshold);
+        if leaf_node || errors_vec[node.errors_vec_index(grid_size)] <= error_threshold {
+            nodes.push(node);
+        } else {
+            stack.push(right_child);
+            stack.push(left_child);
+        }
+    }
     nodes
 }
 
-fn select_nodes_for_heightmap(
-    size: f32,
-    errors_vec: &Vec<f32>,
-    nodes: &mut Vec<BinaryNode>,
-    triangle_index: u32,
-    error_threshold: f32,
-) {
+/// Threshold for `select_nodes` giving the most detailed mesh with at most `max_triangles`,
+/// found by splitting the triangles with the highest error first. The two triangles sharing
+/// a hypotenuse share its midpoint's error and a parent's error is never below its children's,
+/// so every triangle with the same error is split in the same round. Stopping between rounds
+/// leaves the mesh `select_nodes` builds for that round's error, which is free of cracks.
+/// Locked triangles are always split, even past the budget.
+fn budget_error_threshold(size: f32, errors_vec: &[f32], max_triangles: usize) -> f32 {
     let grid_size = size + 1.;
-    let side = size;
-
-    let node = BinaryNode::from_triangle_index(triangle_index);
-
-    let (left_child, right_child) = node.children_ids();
-
-    warn!(
-        "parent: {:?}\nleft: {:?}\nright: {:?}",
-        node, left_child, right_child
-    );
-
-    let number_of_last_level_triangles = side * side * 2.;
-    let number_of_triangles = side * side * 2. - 2. + number_of_last_level_triangles;
-    warn!("number of triangles: {}", number_of_triangles);
-
-    let has_children = right_child.triangle_index() < number_of_triangles as u32;
-    let leaf_node = !has_children;
-
-    let this_triangle_errors_vec_index = node.errors_vec_index(grid_size);
-    warn!("this triangle error index: {this_triangle_errors_vec_index}");
-    let this_triangle_error = errors_vec[this_triangle_errors_vec_index];
-
-    warn!("this triangle error: {this_triangle_error}");
-
-    let error_within_threshold = this_triangle_error <= error_threshold;
+    let side = size as u64;
+    let number_of_last_level_triangles = side * side * 2;
+    let number_of_triangles = side * side * 2 - 2 + number_of_last_level_triangles;
+
+    // errors are never negative, so their bits sort the same way as their values
+    let mut heap = BinaryHeap::<(u32, u64)>::new();
+    let push = |heap: &mut BinaryHeap<(u32, u64)>, node: BinaryNode| {
+        let (_, right_child) = node.children_ids();
+        if right_child.triangle_index() < number_of_triangles {
+            let error = errors_vec[node.errors_vec_index(grid_size)];
+            heap.push((error.to_bits(), *node.as_ref()));
+        }
+    };
+    push(&mut heap, BinaryNode::from_triangle_index(0));
+    push(&mut heap, BinaryNode::from_triangle_index(1));
+
+    let mut triangles = 2;
+    while let Some(&(round_error, _)) = heap.peek() {
+        let mut round_triangles = triangles;
+        while let Some(&(error, id)) = heap.peek() {
+            if error < round_error {
+                break;
+            }
+            heap.pop();
+            // one triangle becomes two
+            round_triangles += 1;
+            let (left_child, right_child) = BinaryNode::from(id).children_ids();
+            push(&mut heap, left_child);
+            push(&mut heap, right_child);
+        }
 
-    if error_within_threshold || leaf_node {
-        warn!("adding to nodes: {}", node.as_ref());
-        nodes.push(node);
-    } else {
-        warn!(
-            "node: {} error is too high, iterating through children triangles\nleft: {}\nright: {}\n",
-            node.as_ref(), left_child.triangle_index(), right_child.triangle_index()
-        );
-        select_nodes_for_heightmap(
-            size,
-            errors_vec,
-            nodes,
-            left_child.triangle_index(),
-            error_threshold,
-        );
-        select_nodes_for_heightmap(
-            size,
-            errors_vec,
-            nodes,
-            right_child.triangle_index(),
-            error_threshold,
-        );
+        if round_triangles > max_triangles && round_error != f32::MAX.to_bits() {
+            return f32::from_bits(round_error);
+        }
+        triangles = round_triangles;
     }
+    // everything fits, split down to the finest level
+    f32::NEG_INFINITY
 }
 
 mod tests {
     #![allow(unused)]
-    use bevy::scene::ron::error;
+    use bevy::{
+        math::{Vec2, Vec3Swizzles},
+        scene::ron::error,
+    };
     use noise::{Fbm, Perlin};
 
     use crate::world::{noise::NoiseSampler, rtin::BinaryNode};
 
-    use super::{get_errors_vec, select_nodes};
+    use super::{
+        build_chunk_terrain_from_sampler, build_chunk_terrain_with_budget,
+        build_terrain_from_sampler, build_terrain_rect_from_sampler, build_terrain_with_budget,
+        get_errors_vec, sample_height_grid, sampler_normal, select_nodes, InvalidExtent,
+        OffsetSampler, PlaneSampler, TerrainMeshData,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     #[test]
     fn errors_vec_correct() {
         let noise = Fbm::<Perlin>::new(69);
         let size = 4.;
         let sampler = NoiseSampler::single_layer(noise);
-        let errors = get_errors_vec(&sampler, size);
+        let errors = get_errors_vec(&sample_height_grid(&sampler, size), |_| false);
         assert_eq!(16, errors.len());
         println!("errors: {:?}", errors);
     }
 
+    fn projected_area(terrain: &TerrainMeshData) -> f32 {
+        terrain
+            .indices
+            .chunks_exact(3)
+            .map(|tri| {
+                let [a, b, c] = [0, 1, 2].map(|i| terrain.vertices[tri[i] as usize].xz());
+                (b - a).perp_dot(c - a).abs() / 2.
+            })
+            .sum()
+    }
+
+    #[test]
+    fn rect_terrain_clipped_to_extent() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        // single block clipped on two sides, then three blocks along x
+        for (width, depth) in [(30., 18.), (70., 20.)] {
+            let terrain =
+                build_terrain_rect_from_sampler(&sampler, 10., width, depth, 0.05).unwrap();
+
+            assert!(terrain
+                .vertices
+                .iter()
+                .all(|v| v.x >= 0. && v.z >= 0. && v.x <= width && v.z <= depth));
+            let far_x = terrain.vertices.iter().filter(|v| v.x == width).count();
+            let far_z = terrain.vertices.iter().filter(|v| v.z == depth).count();
+            assert_eq!(far_x, depth as usize + 1);
+            assert_eq!(far_z, width as usize + 1);
+
+            // no gaps and no overlaps, including along the seams between blocks
+            assert!((projected_area(&terrain) - width * depth).abs() < 1e-2);
+            let mut positions: Vec<_> = terrain
+                .vertices
+                .iter()
+                .map(|v| (v.x as u32, v.z as u32))
+                .collect();
+            positions.sort();
+            positions.dedup();
+            assert_eq!(positions.len(), terrain.vertices.len());
+        }
+    }
+
+    #[test]
+    fn rect_terrain_square_matches_plain_build() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        let rect = build_terrain_rect_from_sampler(&sampler, 10., 32., 32., 0.05).unwrap();
+        let plain = build_terrain_from_sampler(&sampler, 10., 32., 0.05);
+        assert_eq!(rect.vertices, plain.vertices);
+        assert_eq!(rect.indices, plain.indices);
+    }
+
+    #[test]
+    fn rect_terrain_rejects_partial_cells() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        for (width, depth) in [(30.5, 18.), (30., 0.5), (0., 16.), (f32::NAN, 16.)] {
+            let result = build_terrain_rect_from_sampler(&sampler, 10., width, depth, 0.05);
+            assert!(
+                matches!(result, Err(InvalidExtent { .. })),
+                "{width} x {depth} was built"
+            );
+        }
+    }
+
+    struct CountingSampler {
+        inner: NoiseSampler,
+        calls: AtomicUsize,
+    }
+
+    impl PlaneSampler for CountingSampler {
+        fn get(&self, x: f32, y: f32) -> f32 {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.inner.get(x, y)
+        }
+    }
+
+    #[test]
+    fn height_grid_matches_sampler() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        let grid = sample_height_grid(&sampler, 33.);
+        assert_eq!(grid.data.len(), 33 * 33);
+        for (x, y) in [(0, 0), (32, 0), (0, 32), (17, 5), (32, 32)] {
+            assert_eq!(grid.get(x, y), sampler.get(x as f32, y as f32));
+        }
+    }
+
+    #[test]
+    fn sampler_called_once_per_vertex() {
+        let sampler = CountingSampler {
+            inner: NoiseSampler::single_layer(Fbm::<Perlin>::new(69)),
+            calls: AtomicUsize::new(0),
+        };
+        let size = 32.;
+        build_terrain_from_sampler(&sampler, 10., size, 0.01);
+        // plus the ring just outside the grid used for the border normals
+        assert_eq!(sampler.calls.load(Ordering::Relaxed), 33 * 33 + 4 * 33);
+    }
+
+    #[test]
+    fn normals_match_sampler() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        let terrain = build_terrain_from_sampler(&sampler, 10., 32., 0.05);
+        assert_eq!(terrain.normals.len(), terrain.vertices.len());
+        for (v, n) in terrain.vertices.iter().zip(&terrain.normals) {
+            let expected = sampler_normal(&sampler, v.x, v.z, 10.);
+            assert!(n.abs_diff_eq(expected, 1e-6), "{v}: {n} != {expected}");
+        }
+    }
+
+    #[test]
+    fn chunk_normals_match_across_edge() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        let size = 16.;
+        let left = build_chunk_terrain_from_sampler(&sampler, 10., size, 0.05);
+        let right = build_chunk_terrain_from_sampler(
+            &OffsetSampler::new(&sampler, Vec2::new(size, 0.)),
+            10.,
+            size,
+            0.02,
+        );
+
+        let edge = |terrain: &TerrainMeshData, x: f32| {
+            let mut edge: Vec<_> = terrain
+                .vertices
+                .iter()
+                .zip(&terrain.normals)
+                .filter(|(v, _)| v.x == x)
+                .map(|(v, n)| (v.z as u32, *n))
+                .collect();
+            edge.sort_by_key(|(z, _)| *z);
+            edge
+        };
+        assert_eq!(edge(&left, size), edge(&right, 0.));
+    }
+
+    #[test]
+    fn chunk_edges_keep_every_vertex() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        let size = 16.;
+        let coarse = build_chunk_terrain_from_sampler(&sampler, 10., size, 1000.);
+        let fine = build_chunk_terrain_from_sampler(&sampler, 10., size, 0.);
+
+        for terrain in [&coarse, &fine] {
+            let on_edge = |v: &&bevy::math::Vec3| {
+                v.x == 0. || v.z == 0. || v.x == size || v.z == size
+            };
+            assert_eq!(terrain.vertices.iter().filter(on_edge).count(), 4 * size as usize);
+        }
+        assert!(coarse.indices.len() < fine.indices.len());
+    }
+
+    /// Vertices lying inside another triangle's edge, where the mesh would crack
+    fn t_junctions(terrain: &TerrainMeshData) -> usize {
+        let mut count = 0;
+        for tri in terrain.indices.chunks_exact(3) {
+            for (i, j) in [(0, 1), (1, 2), (2, 0)] {
+                let a = terrain.vertices[tri[i] as usize].xz();
+                let b = terrain.vertices[tri[j] as usize].xz();
+                count += terrain
+                    .vertices
+                    .iter()
+                    .map(|v| v.xz())
+                    .filter(|v| {
+                        let t = (*v - a).dot(b - a) / (b - a).length_squared();
+                        (b - a).perp_dot(*v - a) == 0. && t > 0. && t < 1.
+                    })
+                    .count();
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn budget_caps_triangles() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        let size = 16.;
+        let mut last = 0;
+        for budget in [2, 16, 100, 300] {
+            let terrain = build_terrain_with_budget(&sampler, 10., size, budget);
+            let triangles = terrain.indices.len() / 3;
+            assert!(triangles <= budget && triangles >= last);
+            assert_eq!(t_junctions(&terrain), 0);
+            assert!((projected_area(&terrain) - size * size).abs() < 1e-2);
+            last = triangles;
+        }
+
+        let full = build_terrain_with_budget(&sampler, 10., size, usize::MAX);
+        assert_eq!(full.indices.len() / 3, 16 * 16 * 2);
+    }
+
+    #[test]
+    fn budget_matches_threshold_build() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        let threshold = build_terrain_from_sampler(&sampler, 10., 32., 0.05);
+        let budget = build_terrain_with_budget(&sampler, 10., 32., threshold.indices.len() / 3);
+        assert_eq!(budget.indices, threshold.indices);
+    }
+
+    #[test]
+    fn chunk_budget_keeps_edges() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        let size = 16.;
+        let terrain = build_chunk_terrain_with_budget(&sampler, 10., size, 2);
+        let on_edge = |v: &&bevy::math::Vec3| v.x == 0. || v.z == 0. || v.x == size || v.z == size;
+        assert_eq!(
+            terrain.vertices.iter().filter(on_edge).count(),
+            4 * size as usize
+        );
+        assert_eq!(t_junctions(&terrain), 0);
+    }
+
+    #[test]
+    fn stats_describe_build() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        let terrain = build_terrain_from_sampler(&sampler, 10., 32., 0.02);
+        let stats = terrain.stats.clone().unwrap();
+        assert_eq!(stats.vertices, terrain.vertices.len());
+        assert_eq!(stats.triangles, terrain.indices.len() / 3);
+        assert_eq!(stats.nodes_per_level.iter().sum::<usize>(), stats.triangles);
+        assert_eq!(stats.nodes_per_level[0], 0);
+        assert_eq!(stats.error_after.count, stats.triangles);
+        assert!(stats.error_after.max <= 0.02);
+        assert!(stats.error_before.max > stats.error_after.max);
+        assert!(terrain
+            .vertices
+            .iter()
+            .all(|v| v.y >= stats.min_height && v.y <= stats.max_height));
+
+        let full = build_terrain_with_budget(&sampler, 10., 32., usize::MAX);
+        let stats = full.stats.unwrap();
+        assert_eq!(stats.error_threshold, f32::NEG_INFINITY);
+        assert_eq!(stats.error_after.max, 0.);
+        assert_eq!(*stats.nodes_per_level.last().unwrap(), 32 * 32 * 2);
+
+        let rect = build_terrain_rect_from_sampler(&sampler, 10., 40., 24., 0.02).unwrap();
+        let stats = rect.stats.unwrap();
+        assert_eq!(stats.triangles, rect.indices.len() / 3);
+        assert_eq!(stats.vertices, rect.vertices.len());
+    }
+
     #[test]
     fn select_nodes_works() {
         let size = 4.;
@@ -0,0 +1,123 @@
+use super::TerrainMeshData;
+use bevy::math::{Vec3, Vec3Swizzles};
+use std::collections::HashMap;
+
+impl TerrainMeshData {
+    /// Hangs a strip `depth` deep under each of the four borders, facing outwards, so a
+    /// neighbouring chunk at another LOD or a float mismatch along the seam never shows a gap.
+    /// Skirt vertices copy the normal of the border vertex above them, and share its x and z
+    /// so `into_mesh` gives them the same UVs.
+    pub fn add_skirts(&mut self, depth: f32) {
+        let Some(first) = self.vertices.first() else {
+            return;
+        };
+        let (min, max) = self
+            .vertices
+            .iter()
+            .fold((first.xz(), first.xz()), |(min, max), v| {
+                (min.min(v.xz()), max.max(v.xz()))
+            });
+        let has_normals = self.normals.len() == self.vertices.len();
+        // skirt vertices sit on the borders too, only the mesh's own vertices are hung from
+        let top_count = self.vertices.len() as u32;
+        // border vertex to the skirt vertex under it, corners are shared by two borders
+        let mut skirt_index = HashMap::<u32, u32>::new();
+
+        for (outward, line) in [
+            (Vec3::NEG_X, min.x),
+            (Vec3::X, max.x),
+            (Vec3::NEG_Z, min.y),
+            (Vec3::Z, max.y),
+        ] {
+            let along_z = outward.x != 0.;
+            let mut border: Vec<u32> = (0..top_count)
+                .filter(|i| {
+                    let v = self.vertices[*i as usize];
+                    if along_z {
+                        v.x == line
+                    } else {
+                        v.z == line
+                    }
+                })
+                .collect();
+            border.sort_by(|a, b| {
+                let (a, b) = (self.vertices[*a as usize], self.vertices[*b as usize]);
+                if along_z {
+                    a.z.total_cmp(&b.z)
+                } else {
+                    a.x.total_cmp(&b.x)
+                }
+            });
+
+            for pair in border.windows(2) {
+                let (mut top_a, mut top_b) = (pair[0], pair[1]);
+                let (a, b) = (self.vertices[top_a as usize], self.vertices[top_b as usize]);
+                if (b - a).cross(Vec3::NEG_Y).dot(outward) < 0. {
+                    std::mem::swap(&mut top_a, &mut top_b);
+                }
+                let bottom_a = self.skirt_vertex(top_a, depth, has_normals, &mut skirt_index);
+                let bottom_b = self.skirt_vertex(top_b, depth, has_normals, &mut skirt_index);
+                self.indices
+                    .extend([top_a, top_b, bottom_a, top_b, bottom_b, bottom_a]);
+            }
+        }
+    }
+
+    fn skirt_vertex(
+        &mut self,
+        top: u32,
+        depth: f32,
+        has_normals: bool,
+        skirt_index: &mut HashMap<u32, u32>,
+    ) -> u32 {
+        *skirt_index.entry(top).or_insert_with(|| {
+            self.vertices
+                .push(self.vertices[top as usize] - Vec3::Y * depth);
+            if has_normals {
+                self.normals.push(self.normals[top as usize]);
+            }
+            self.vertices.len() as u32 - 1
+        })
+    }
+}
+
+mod tests {
+    #![allow(unused)]
+    use crate::world::{noise::NoiseSampler, rtin::build_chunk_terrain_from_sampler};
+    use bevy::math::{Vec3, Vec3Swizzles};
+    use noise::{Fbm, Perlin};
+
+    #[test]
+    fn skirts_hang_under_borders() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        let size = 16.;
+        let mut terrain = build_chunk_terrain_from_sampler(&sampler, 10., size, 0.05);
+        let (vertices, triangles) = (terrain.vertices.len(), terrain.indices.len() / 3);
+        terrain.add_skirts(3.);
+
+        // 17 vertices along each border, corners shared
+        assert_eq!(terrain.vertices.len(), vertices + 4 * size as usize);
+        assert_eq!(terrain.indices.len() / 3, triangles + 4 * 2 * size as usize);
+        assert_eq!(terrain.normals.len(), terrain.vertices.len());
+
+        for (i, skirt) in terrain.vertices.iter().enumerate().skip(vertices) {
+            let top = terrain.vertices[..vertices]
+                .iter()
+                .position(|v| v.xz() == skirt.xz())
+                .unwrap();
+            assert_eq!(terrain.vertices[top].y - 3., skirt.y);
+            assert_eq!(terrain.normals[top], terrain.normals[i]);
+        }
+
+        let center = Vec3::new(size / 2., 0., size / 2.);
+        for tri in triangles..terrain.indices.len() / 3 {
+            let normal = terrain.face_normal(tri);
+            let a = terrain.vertices[terrain.indices[tri * 3] as usize];
+            assert!(normal.y.abs() < 1e-6);
+            assert!(
+                normal.dot((a - center).with_y(0.)) > 0.,
+                "skirt faces inwards"
+            );
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use bevy::prelude::*;
+use std::time::Duration;
+
+/// What a terrain build produced and how long it took, for tuning `error_threshold` per map.
+/// Errors are in sampler units like the threshold, heights in mesh units.
+#[derive(Reflect, Debug, Clone, Default, PartialEq)]
+pub struct TerrainStats {
+    pub vertices: usize,
+    pub triangles: usize,
+    /// Threshold the mesh was simplified down to, found from the budget in budget builds
+    pub error_threshold: f32,
+    /// Every triangle of the full tree, as if nothing were simplified away
+    pub error_before: ErrorStats,
+    /// The triangles that made it into the mesh
+    pub error_after: ErrorStats,
+    /// Kept triangles per level of the tree, the two root triangles are level 0
+    pub nodes_per_level: Vec<usize>,
+    pub min_height: f32,
+    pub max_height: f32,
+    pub timings: PhaseTimings,
+}
+
+/// Locked triangles aren't counted, their error only forces them to split
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq)]
+pub struct ErrorStats {
+    pub max: f32,
+    pub mean: f32,
+    pub count: usize,
+}
+
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq)]
+pub struct PhaseTimings {
+    /// Height grid and the ring of samples around it used for normals
+    pub sampling: Duration,
+    pub errors: Duration,
+    /// Picking the triangles, including the threshold search of budget builds
+    pub selection: Duration,
+    pub mesh: Duration,
+}
+
+impl ErrorStats {
+    pub fn new(errors: impl IntoIterator<Item = f32>) -> Self {
+        let (mut max, mut sum, mut count) = (0f32, 0f64, 0);
+        for error in errors.into_iter().filter(|e| *e < f32::MAX) {
+            max = max.max(error);
+            sum += error as f64;
+            count += 1;
+        }
+        let mean = if count == 0 {
+            0.
+        } else {
+            (sum / count as f64) as f32
+        };
+        Self { max, mean, count }
+    }
+
+    pub fn merge(&self, other: &Self) -> Self {
+        let count = self.count + other.count;
+        let mean = if count == 0 {
+            0.
+        } else {
+            (self.mean * self.count as f32 + other.mean * other.count as f32) / count as f32
+        };
+        Self {
+            max: self.max.max(other.max),
+            mean,
+            count,
+        }
+    }
+}
+
+impl PhaseTimings {
+    pub fn total(&self) -> Duration {
+        self.sampling + self.errors + self.selection + self.mesh
+    }
+
+    pub fn merge(&self, other: &Self) -> Self {
+        Self {
+            sampling: self.sampling + other.sampling,
+            errors: self.errors + other.errors,
+            selection: self.selection + other.selection,
+            mesh: self.mesh + other.mesh,
+        }
+    }
+}
+
+impl TerrainStats {
+    /// Stats of two blocks built separately, the mesh counts are left to the caller since the
+    /// blocks share vertices
+    pub fn merge(&self, other: &Self) -> Self {
+        let levels = self.nodes_per_level.len().max(other.nodes_per_level.len());
+        let level = |stats: &Self, i: usize| stats.nodes_per_level.get(i).copied().unwrap_or(0);
+        Self {
+            vertices: self.vertices,
+            triangles: self.triangles,
+            error_threshold: self.error_threshold.max(other.error_threshold),
+            error_before: self.error_before.merge(&other.error_before),
+            error_after: self.error_after.merge(&other.error_after),
+            nodes_per_level: (0..levels)
+                .map(|i| level(self, i) + level(other, i))
+                .collect(),
+            min_height: self.min_height.min(other.min_height),
+            max_height: self.max_height.max(other.max_height),
+            timings: self.timings.merge(&other.timings),
+        }
+    }
+}
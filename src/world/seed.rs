@@ -0,0 +1,137 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+use std::num::ParseIntError;
+
+/// Every random choice made while generating the world comes from this seed, so the same seed
+/// always produces the same world. Pass `--seed <number>` on the command line to pick it,
+/// otherwise a random one is used and logged at startup.
+#[derive(Resource, Reflect, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[reflect(Resource)]
+pub struct WorldSeed(pub u64);
+
+/// Independent parts of generation, each gets its own sub-seed so adding random choices to one
+/// doesn't change the others. The values are part of the derivation and must not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SeedStream {
+    TerrainNoise = 1,
+    WaveCollapse = 2,
+    Items = 3,
+    Npcs = 4,
+}
+
+impl Default for WorldSeed {
+    fn default() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl WorldSeed {
+    /// Reads `--seed <number>` or `--seed=<number>`, `None` if no seed was passed
+    pub fn from_args(
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<Option<Self>, ParseIntError> {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let value = match arg.strip_prefix("--seed") {
+                Some("") => args.next(),
+                Some(rest) => rest.strip_prefix('=').map(str::to_owned),
+                None => continue,
+            };
+            let Some(value) = value else {
+                continue;
+            };
+            return value.parse().map(|seed| Some(Self(seed)));
+        }
+        Ok(None)
+    }
+
+    /// Same as `from_args`, but a missing or malformed seed falls back to a random one
+    pub fn from_args_or_random(args: impl IntoIterator<Item = String>) -> Self {
+        Self::from_args(args)
+            .unwrap_or_else(|err| {
+                warn!("ignoring --seed, it expects a number: {err}");
+                None
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn derive(&self, stream: SeedStream) -> u64 {
+        splitmix64(self.0 ^ splitmix64(stream as u64))
+    }
+
+    /// Noise functions only take 32 bit seeds
+    pub fn derive_u32(&self, stream: SeedStream) -> u32 {
+        (self.derive(stream) >> 32) as u32
+    }
+
+    pub fn rng(&self, stream: SeedStream) -> StdRng {
+        StdRng::seed_from_u64(self.derive(stream))
+    }
+}
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+pub fn log_world_seed(seed: Res<WorldSeed>) {
+    info!(
+        "world seed: {}, run with --seed {} to get this world again",
+        seed.0, seed.0
+    );
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::{SeedStream, WorldSeed};
+    use rand::Rng;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn seed_from_args() {
+        assert_eq!(
+            WorldSeed::from_args(args(&["game", "--seed", "42"])),
+            Ok(Some(WorldSeed(42)))
+        );
+        assert_eq!(
+            WorldSeed::from_args(args(&["game", "--seed=7"])),
+            Ok(Some(WorldSeed(7)))
+        );
+        assert_eq!(WorldSeed::from_args(args(&["game", "--seeds"])), Ok(None));
+        assert_eq!(WorldSeed::from_args(args(&["game"])), Ok(None));
+    }
+
+    #[test]
+    fn seed_must_be_number() {
+        assert!(WorldSeed::from_args(args(&["game", "--seed", "banana"])).is_err());
+        assert!(WorldSeed::from_args(args(&["game", "--seed=-1"])).is_err());
+        // still gets a seed to run with
+        WorldSeed::from_args_or_random(args(&["game", "--seed", "banana"]));
+    }
+
+    #[test]
+    fn streams_are_deterministic_and_independent() {
+        let seed = WorldSeed(42);
+        assert_eq!(
+            seed.derive(SeedStream::Items),
+            WorldSeed(42).derive(SeedStream::Items)
+        );
+        assert_ne!(
+            seed.derive(SeedStream::Items),
+            seed.derive(SeedStream::Npcs)
+        );
+        assert_ne!(
+            seed.derive(SeedStream::Items),
+            WorldSeed(43).derive(SeedStream::Items)
+        );
+
+        let a: u64 = seed.rng(SeedStream::WaveCollapse).gen();
+        let b: u64 = seed.rng(SeedStream::WaveCollapse).gen();
+        assert_eq!(a, b);
+    }
+}
@@ -0,0 +1,323 @@
+use super::{
+    biome::{BiomeSampler, BiomeSource},
+    deformation::{DeformedSampler, HeightLayer},
+    jobs::{TerrainBuildRequest, TerrainBuildTask},
+    rtin::{coloring::TerrainColoring, OffsetSampler, PlaneSampler},
+    terrain::TerrainColliderMode,
+    GROUND_Y,
+};
+use crate::player::{world::PlayerInWorld, LogicalPlayer};
+use bevy::{math::Vec3Swizzles, prelude::*, utils::HashMap};
+use std::sync::Arc;
+
+pub struct TerrainStreamingPlugin;
+
+impl Plugin for TerrainStreamingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LoadedChunks>().add_systems(
+            Update,
+            (stream_terrain_chunks, update_chunk_lods)
+                .chain()
+                .run_if(resource_exists::<TerrainStreaming>),
+        );
+    }
+}
+
+/// Describes how terrain chunks are built and how many of them are kept around the player.
+/// Every chunk samples the same `sampler` at its world offset, so neighbouring edges line up.
+#[derive(Resource)]
+pub struct TerrainStreaming {
+    pub sampler: Arc<dyn PlaneSampler>,
+    /// Width of a chunk in world units, must be a power of 2
+    pub chunk_size: f32,
+    pub height_multiplier: f32,
+    /// Sorted by distance, chunks past the last band use the last band's threshold
+    pub lod_bands: Vec<LodBand>,
+    /// Chunks with any part closer than this to the player are loaded
+    pub view_radius: f32,
+    /// Hard cap on loaded chunks, the furthest ones are dropped first
+    pub max_loaded_chunks: usize,
+    pub collider_mode: TerrainColliderMode,
+    pub coloring: Option<TerrainColoring>,
+    /// Skirts hide the gaps left while a chunk's neighbour is still built at another LOD
+    pub skirt_depth: Option<f32>,
+    /// Set when `sampler` blends biomes, chunk vertices are then tagged with their biome
+    pub biomes: Option<Arc<BiomeSampler>>,
+    /// Brush strokes stacked on `sampler`, see `DeformTerrain`
+    pub deformation: Arc<HeightLayer>,
+}
+
+/// Chunks closer to the camera than `distance` are built with `error_threshold`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LodBand {
+    pub distance: f32,
+    pub error_threshold: f32,
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TerrainChunk {
+    pub coord: IVec2,
+    /// Index into `TerrainStreaming::lod_bands` the current mesh was built with
+    pub lod: usize,
+}
+
+/// Chunk coordinate to the entity holding that chunk
+#[derive(Resource, Debug, Default)]
+pub struct LoadedChunks(pub HashMap<IVec2, Entity>);
+
+impl TerrainStreaming {
+    pub fn new(sampler: impl PlaneSampler + 'static) -> Self {
+        Self {
+            sampler: Arc::new(sampler),
+            chunk_size: 64.,
+            height_multiplier: 50.,
+            lod_bands: vec![
+                LodBand {
+                    distance: 64.,
+                    error_threshold: 0.005,
+                },
+                LodBand {
+                    distance: 128.,
+                    error_threshold: 0.02,
+                },
+                LodBand {
+                    distance: 256.,
+                    error_threshold: 0.08,
+                },
+            ],
+            view_radius: 192.,
+            max_loaded_chunks: 64,
+            // keeps physics at full resolution whatever LOD band a chunk is in
+            collider_mode: TerrainColliderMode::Heightfield,
+            coloring: Some(TerrainColoring::default()),
+            skirt_depth: Some(4.),
+            biomes: None,
+            deformation: Arc::default(),
+        }
+    }
+
+    pub fn chunk_coord(&self, position: Vec2) -> IVec2 {
+        (position / self.chunk_size).floor().as_ivec2()
+    }
+
+    /// World XZ position of the chunk's (0, 0) grid corner
+    pub fn chunk_origin(&self, coord: IVec2) -> Vec2 {
+        coord.as_vec2() * self.chunk_size
+    }
+
+    /// Distance from `position` to the closest point of the chunk
+    pub fn distance_to_chunk(&self, coord: IVec2, position: Vec2) -> f32 {
+        let min = self.chunk_origin(coord);
+        let max = min + Vec2::splat(self.chunk_size);
+        position.distance(position.clamp(min, max))
+    }
+
+    /// Chunks that should be loaded for a player at `position`, closest first
+    pub fn chunks_in_view(&self, position: Vec2) -> Vec<IVec2> {
+        let center = self.chunk_coord(position);
+        let reach = (self.view_radius / self.chunk_size).ceil() as i32;
+
+        let mut coords = vec![];
+        for z in -reach..=reach {
+            for x in -reach..=reach {
+                let coord = center + IVec2::new(x, z);
+                if self.distance_to_chunk(coord, position) <= self.view_radius {
+                    coords.push(coord);
+                }
+            }
+        }
+
+        coords.sort_by(|a, b| {
+            self.distance_to_chunk(*a, position)
+                .total_cmp(&self.distance_to_chunk(*b, position))
+        });
+        coords.truncate(self.max_loaded_chunks);
+        coords
+    }
+
+    /// LOD band for a chunk seen from `camera`
+    pub fn chunk_lod(&self, coord: IVec2, camera: Vec2) -> usize {
+        let distance = self.distance_to_chunk(coord, camera);
+        self.lod_bands
+            .iter()
+            .position(|band| distance <= band.distance)
+            .unwrap_or(self.lod_bands.len().saturating_sub(1))
+    }
+
+    /// Chunks whose mesh reads any grid point inside `rect`. Points on a border belong to the
+    /// chunks on both sides, and the normals at a border read one point past it, so a chunk
+    /// reads the points from one before its origin up to one past its far edge.
+    pub fn chunks_overlapping(&self, rect: IRect) -> Vec<IVec2> {
+        let min = self.chunk_coord((rect.min - 2).as_vec2());
+        let max = self.chunk_coord((rect.max + 1).as_vec2());
+        let mut coords = vec![];
+        for z in min.y..=max.y {
+            for x in min.x..=max.x {
+                coords.push(IVec2::new(x, z));
+            }
+        }
+        coords
+    }
+
+    /// Build request for a chunk, placed at its world origin
+    pub fn chunk_request(&self, chunk: TerrainChunk) -> TerrainBuildRequest {
+        let coord = chunk.coord;
+        let origin = self.chunk_origin(coord);
+        let sampler = DeformedSampler {
+            sampler: self.sampler.clone(),
+            layer: self.deformation.clone(),
+            height_multiplier: self.height_multiplier,
+        };
+        let biomes = self
+            .biomes
+            .clone()
+            .map(|biomes| Arc::new(OffsetSampler::new(biomes, origin)) as Arc<dyn BiomeSource>);
+        TerrainBuildRequest {
+            sampler: Arc::new(OffsetSampler::new(sampler, origin)),
+            height_multiplier: self.height_multiplier,
+            width: self.chunk_size,
+            depth: self.chunk_size,
+            error_threshold: self.lod_bands[chunk.lod].error_threshold,
+            // edges are locked to full resolution so chunks in different bands still meet exactly
+            lock_edges: true,
+            collider_mode: self.collider_mode,
+            coloring: self.coloring.clone(),
+            skirt_depth: self.skirt_depth,
+            biomes,
+            transform: Transform::from_xyz(origin.x, GROUND_Y, origin.y),
+            name: Name::new(format!("Terrain Chunk {} {}", coord.x, coord.y)),
+        }
+    }
+}
+
+/// Chunks are spawned with only a `TerrainBuildTask`, their `TerrainBundle` is inserted once
+/// the build finishes. Despawning a chunk that is still building cancels its task.
+pub fn stream_terrain_chunks(
+    mut commands: Commands,
+    streaming: Res<TerrainStreaming>,
+    mut loaded: ResMut<LoadedChunks>,
+    player_q: Query<&GlobalTransform, With<LogicalPlayer>>,
+    camera_q: Query<&GlobalTransform, With<PlayerInWorld>>,
+) {
+    let Ok(player_transform) = player_q.get_single() else {
+        return;
+    };
+    let player_position = player_transform.translation().xz();
+    let camera_position = camera_q
+        .get_single()
+        .map(|t| t.translation().xz())
+        .unwrap_or(player_position);
+    let wanted = streaming.chunks_in_view(player_position);
+
+    loaded.0.retain(|coord, entity| {
+        let keep = wanted.contains(coord);
+        if !keep {
+            commands.entity(*entity).despawn_recursive();
+        }
+        keep
+    });
+
+    for coord in wanted {
+        if loaded.0.contains_key(&coord) {
+            continue;
+        }
+        let chunk = TerrainChunk {
+            coord,
+            lod: streaming.chunk_lod(coord, camera_position),
+        };
+        let task = TerrainBuildTask::spawn(streaming.chunk_request(chunk));
+        let entity = commands.spawn((chunk, task)).id();
+        loaded.0.insert(coord, entity);
+    }
+}
+
+/// Rebuilds chunks whose distance to the camera moved them into a different LOD band. The old
+/// mesh stays until the new one is ready, and a build still pending for the old band is dropped.
+pub fn update_chunk_lods(
+    mut commands: Commands,
+    streaming: Res<TerrainStreaming>,
+    camera_q: Query<&GlobalTransform, With<PlayerInWorld>>,
+    mut chunk_q: Query<(Entity, &mut TerrainChunk)>,
+) {
+    let Ok(camera_transform) = camera_q.get_single() else {
+        return;
+    };
+    let camera_position = camera_transform.translation().xz();
+
+    for (entity, mut chunk) in chunk_q.iter_mut() {
+        let lod = streaming.chunk_lod(chunk.coord, camera_position);
+        if lod == chunk.lod {
+            continue;
+        }
+        chunk.lod = lod;
+        let task = TerrainBuildTask::spawn(streaming.chunk_request(*chunk));
+        commands.entity(entity).insert(task);
+    }
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::TerrainStreaming;
+    use crate::world::noise::NoiseSampler;
+    use bevy::math::{IRect, IVec2, Vec2};
+    use noise::{Fbm, Perlin};
+
+    fn streaming() -> TerrainStreaming {
+        let mut streaming = TerrainStreaming::new(NoiseSampler::single_layer(Fbm::<Perlin>::new(5)));
+        streaming.chunk_size = 16.;
+        streaming.view_radius = 20.;
+        streaming
+    }
+
+    #[test]
+    fn chunk_coord_correct() {
+        let streaming = streaming();
+        assert_eq!(streaming.chunk_coord(Vec2::new(0., 0.)), IVec2::new(0, 0));
+        assert_eq!(streaming.chunk_coord(Vec2::new(15.9, 16.)), IVec2::new(0, 1));
+        assert_eq!(streaming.chunk_coord(Vec2::new(-0.1, -16.1)), IVec2::new(-1, -2));
+    }
+
+    #[test]
+    fn chunks_in_view_closest_first() {
+        let mut streaming = streaming();
+        let position = Vec2::new(8., 8.);
+        let chunks = streaming.chunks_in_view(position);
+        assert_eq!(chunks[0], IVec2::new(0, 0));
+        assert!(chunks
+            .iter()
+            .all(|c| streaming.distance_to_chunk(*c, position) <= streaming.view_radius));
+        // 3x3 block around the player, corners are 8 * sqrt(2) away
+        assert_eq!(chunks.len(), 9);
+
+        streaming.max_loaded_chunks = 5;
+        let capped = streaming.chunks_in_view(position);
+        assert_eq!(capped.len(), 5);
+        assert_eq!(&capped[..], &chunks[..5]);
+    }
+
+    #[test]
+    fn chunks_overlapping_include_borders() {
+        let streaming = streaming();
+        let inside = streaming.chunks_overlapping(IRect::new(4, 4, 8, 8));
+        assert_eq!(inside, vec![IVec2::new(0, 0)]);
+
+        // x = 16 is the edge between chunk 0 and chunk 1
+        let edge = streaming.chunks_overlapping(IRect::new(14, 4, 16, 8));
+        assert_eq!(edge, vec![IVec2::new(0, 0), IVec2::new(1, 0)]);
+
+        // one point inside chunk 1 still changes the normals along its edge with chunk 0
+        let normals = streaming.chunks_overlapping(IRect::new(17, 4, 20, 8));
+        assert_eq!(normals, vec![IVec2::new(0, 0), IVec2::new(1, 0)]);
+    }
+
+    #[test]
+    fn chunk_lod_follows_bands() {
+        let streaming = streaming();
+        let camera = Vec2::new(8., 8.);
+        assert_eq!(streaming.chunk_lod(IVec2::new(0, 0), camera), 0);
+        assert_eq!(streaming.chunk_lod(IVec2::new(2, 0), camera), 0);
+        assert_eq!(streaming.chunk_lod(IVec2::new(5, 0), camera), 1);
+        assert_eq!(streaming.chunk_lod(IVec2::new(12, 0), camera), 2);
+        assert_eq!(streaming.chunk_lod(IVec2::new(100, 0), camera), 2);
+    }
+}
@@ -1,7 +1,14 @@
-use super::{noise::NoiseSampler, rtin::build_terrain_from_sampler, GROUND_Y};
-use bevy::{color::palettes::css::GREEN, prelude::*};
+use super::{
+    config::{TerrainConfig, TerrainConfigHandle},
+    jobs::TerrainBuildTask,
+    rtin::PlaneSampler,
+    GROUND_Y,
+};
+use bevy::{
+    color::palettes::css::{GREEN, WHITE},
+    prelude::*,
+};
 use bevy_rapier3d::prelude::*;
-use noise::{Fbm, Perlin};
 use std::sync::LazyLock;
 
 #[derive(Component)]
@@ -23,6 +30,60 @@ pub struct TerrainBundle {
     pub material: Handle<StandardMaterial>,
 }
 
+/// How the physics shape of a terrain bundle is built
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TerrainColliderMode {
+    /// Trimesh of the render mesh, so it shares the RTIN simplification
+    #[default]
+    TriMesh,
+    /// Heightfield sampled at every grid vertex, independent of the render mesh's error threshold
+    Heightfield,
+}
+
+impl TerrainColliderMode {
+    pub fn collider(
+        &self,
+        mesh: &Mesh,
+        sampler: &impl PlaneSampler,
+        height_multiplier: f32,
+        width: f32,
+        depth: f32,
+    ) -> Collider {
+        match self {
+            Self::TriMesh => {
+                Collider::from_bevy_mesh(mesh, &ComputedColliderShape::TriMesh).unwrap()
+            }
+            Self::Heightfield => heightfield_collider(sampler, height_multiplier, width, depth),
+        }
+    }
+}
+
+/// Full resolution heightfield covering the same [0, width] x [0, depth] area as the terrain mesh
+pub fn heightfield_collider(
+    sampler: &impl PlaneSampler,
+    height_multiplier: f32,
+    width: f32,
+    depth: f32,
+) -> Collider {
+    let cols = width as usize + 1;
+    let rows = depth as usize + 1;
+    let mut heights = Vec::with_capacity(cols * rows);
+    // rapier reads the heights column major, rows run along z and columns along x
+    for x in 0..cols {
+        for z in 0..rows {
+            heights.push(sampler.get(x as f32, z as f32) * height_multiplier);
+        }
+    }
+
+    let heightfield = Collider::heightfield(heights, rows, cols, Vec3::new(width, 1., depth));
+    // heightfields are centered on their origin while terrain meshes start at it
+    Collider::compound(vec![(
+        Vec3::new(width / 2., 0., depth / 2.),
+        Quat::IDENTITY,
+        heightfield,
+    )])
+}
+
 impl TerrainBundle {
     pub fn new(
         mesh: Mesh,
@@ -30,8 +91,23 @@ impl TerrainBundle {
         materials: &mut ResMut<Assets<StandardMaterial>>,
     ) -> Self {
         let collider = Collider::from_bevy_mesh(&mesh, &ComputedColliderShape::TriMesh).unwrap();
+        Self::with_collider(mesh, collider, meshes, materials)
+    }
+
+    pub fn with_collider(
+        mesh: Mesh,
+        collider: Collider,
+        meshes: &mut ResMut<Assets<Mesh>>,
+        materials: &mut ResMut<Assets<StandardMaterial>>,
+    ) -> Self {
+        // vertex colors are multiplied with the base color, so colored terrain gets a white one
+        let base_color = if mesh.contains_attribute(Mesh::ATTRIBUTE_COLOR) {
+            WHITE
+        } else {
+            GREEN
+        };
         let mesh = meshes.add(mesh);
-        let material = materials.add(Color::from(GREEN));
+        let material = materials.add(Color::from(base_color));
 
         let transform = Transform::from_xyz(0., GROUND_Y, 0.);
         Self {
@@ -49,30 +125,102 @@ impl TerrainBundle {
 }
 
 pub fn is_power_of_2(x: f32) -> bool {
-    let x = x as u32;
-    (x & !(x & (x - 1))) > 0
+    x.fract() == 0. && (x as u32).is_power_of_two()
 }
 
+/// Queues a single terrain build from the loaded config, the bundle is inserted once it
+/// finishes. Does nothing before the config has loaded.
 pub fn spawn_terrain(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    handle: Res<TerrainConfigHandle>,
+    configs: Res<Assets<TerrainConfig>>,
 ) {
-    let mut noise_func = Fbm::<Perlin>::new(5);
-    noise_func.lacunarity = 0.2;
-    noise_func.frequency = 0.0125;
-    noise_func.octaves = 2;
-    noise_func.persistence = 0.2;
-    let size = 256.;
-    let err_threshold = 0.01;
-    let height_multiplier = 50.;
-
-    let sampler = NoiseSampler::single_layer(noise_func);
-
-    assert!(is_power_of_2(size));
-    let terrain = build_terrain_from_sampler(&sampler, height_multiplier, size, err_threshold);
-    let mesh = terrain.into_mesh(false, size);
-
-    let bundle = TerrainBundle::new(mesh, &mut meshes, &mut materials);
-    commands.spawn(bundle);
+    let Some(config) = configs.get(&handle.0) else {
+        return;
+    };
+    commands.spawn(TerrainBuildTask::spawn(config.terrain_request()));
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::heightfield_collider;
+    use crate::world::{config::TerrainConfig, rtin::PlaneSampler};
+    use bevy::math::{Quat, Vec3};
+
+    #[test]
+    fn heightfield_matches_sampler() {
+        let sampler = TerrainConfig::default().sampler();
+        let size = 32.;
+        let height_multiplier = 50.;
+        let collider = heightfield_collider(&sampler, height_multiplier, size, size);
+
+        let ray_start = 1000.;
+        for (x, z) in [(1., 1.), (5., 20.), (16., 16.), (31., 7.), (12., 30.)] {
+            let toi = collider
+                .cast_ray(
+                    Vec3::ZERO,
+                    Quat::IDENTITY,
+                    Vec3::new(x, ray_start, z),
+                    -Vec3::Y,
+                    ray_start * 2.,
+                    true,
+                )
+                .expect("ray should hit the heightfield");
+            let expected = sampler.get(x, z) * height_multiplier;
+            assert!(
+                (ray_start - toi - expected).abs() < 1e-3,
+                "at ({x}, {z}) hit {} but sampler gives {expected}",
+                ray_start - toi
+            );
+        }
+    }
+
+    #[test]
+    fn heightfield_translated_with_terrain() {
+        let sampler = TerrainConfig::default().sampler();
+        let collider = heightfield_collider(&sampler, 50., 16., 16.);
+        let offset = Vec3::new(100., -5., 100.);
+        let toi = collider
+            .cast_ray(
+                offset,
+                Quat::IDENTITY,
+                offset + Vec3::new(3., 1000., 4.),
+                -Vec3::Y,
+                2000.,
+                true,
+            )
+            .unwrap();
+        assert!((1000. - toi - sampler.get(3., 4.) * 50.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn heightfield_rectangular() {
+        let sampler = TerrainConfig::default().sampler();
+        let (width, depth) = (40., 12.);
+        let collider = heightfield_collider(&sampler, 50., width, depth);
+        for (x, z) in [(38., 2.), (3., 11.), (20., 6.)] {
+            let toi = collider
+                .cast_ray(
+                    Vec3::ZERO,
+                    Quat::IDENTITY,
+                    Vec3::new(x, 1000., z),
+                    -Vec3::Y,
+                    2000.,
+                    true,
+                )
+                .expect("ray should hit the heightfield");
+            assert!((1000. - toi - sampler.get(x, z) * 50.).abs() < 1e-3);
+        }
+        // nothing past the depth
+        assert!(collider
+            .cast_ray(
+                Vec3::ZERO,
+                Quat::IDENTITY,
+                Vec3::new(20., 1000., 14.),
+                -Vec3::Y,
+                2000.,
+                true
+            )
+            .is_none());
+    }
 }
@@ -0,0 +1,444 @@
+use super::rtin::TerrainMeshData;
+use bevy::{
+    color::palettes::css::{RED, YELLOW},
+    prelude::*,
+    render::mesh::{Indices, PrimitiveTopology},
+    utils::HashMap,
+};
+
+/// Something wrong with a triangle mesh. Vertex indices point into the mesh's own vertex
+/// buffer, triangle indices count triangles, not indices.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshIssue {
+    /// Triangle with no area
+    DegenerateTriangle { triangle: usize, vertices: [u32; 3] },
+    /// Index past the end of the vertex buffer
+    IndexOutOfRange { triangle: usize, index: u32 },
+    /// Triangle using the same vertex more than once
+    DuplicateIndex { triangle: usize, vertices: [u32; 3] },
+    /// Both triangles run along their shared edge in the same direction, so one of them faces
+    /// the wrong way
+    InconsistentWinding {
+        edge: [u32; 2],
+        triangles: [usize; 2],
+    },
+    /// `vertex` lies inside `edge` of `triangle` without being one of its corners, the
+    /// triangles on the other side can crack away from it
+    TJunction {
+        triangle: usize,
+        edge: [u32; 2],
+        vertex: u32,
+    },
+    /// Edge shared by more than two triangles
+    NonManifoldEdge {
+        edge: [u32; 2],
+        triangles: Vec<usize>,
+    },
+    /// Edges with a triangle on only one side, in the order the triangles wind along them.
+    /// `closed` is false when the chain dead ends, which only happens next to other issues.
+    OpenBoundary { vertices: Vec<u32>, closed: bool },
+}
+
+impl MeshIssue {
+    pub fn is_boundary(&self) -> bool {
+        matches!(self, Self::OpenBoundary { .. })
+    }
+
+    /// Segments outlining the issue, empty if it has no position
+    pub fn lines(&self, positions: &[Vec3]) -> Vec<[Vec3; 2]> {
+        let v = |i: &u32| positions[*i as usize];
+        let outline = |ids: &[u32]| -> Vec<[Vec3; 2]> {
+            (0..ids.len())
+                .map(|i| [v(&ids[i]), v(&ids[(i + 1) % ids.len()])])
+                .collect()
+        };
+        match self {
+            Self::DegenerateTriangle { vertices, .. } | Self::DuplicateIndex { vertices, .. } => {
+                outline(&vertices[..])
+            }
+            Self::IndexOutOfRange { .. } => vec![],
+            Self::InconsistentWinding { edge, .. } | Self::NonManifoldEdge { edge, .. } => {
+                vec![[v(&edge[0]), v(&edge[1])]]
+            }
+            Self::TJunction { edge, vertex, .. } => {
+                vec![[v(&edge[0]), v(vertex)], [v(vertex), v(&edge[1])]]
+            }
+            Self::OpenBoundary { vertices, closed } => {
+                let mut lines = outline(vertices);
+                if !closed {
+                    lines.pop();
+                }
+                lines
+            }
+        }
+    }
+}
+
+/// Result of validating a mesh. Kept on an entity, `draw_mesh_issues` outlines the issues on
+/// top of its mesh.
+#[derive(Component, Debug, Clone, Default, PartialEq)]
+pub struct MeshReport {
+    pub vertex_count: usize,
+    pub triangle_count: usize,
+    pub issues: Vec<MeshIssue>,
+}
+
+impl MeshReport {
+    /// Checks a triangle list. Vertices at exactly the same position are treated as one, since
+    /// meshes split vertices wherever their normals or UVs change, so edges between them still
+    /// count as shared. T-junctions are looked for along open edges, where they leave cracks.
+    pub fn new(vertices: &[Vec3], indices: &[u32]) -> Self {
+        let mut issues = vec![];
+        let mut welded_ids = HashMap::<[u32; 3], u32>::new();
+        let weld: Vec<u32> = vertices
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                let key = v.to_array().map(f32::to_bits);
+                *welded_ids.entry(key).or_insert(i as u32)
+            })
+            .collect();
+
+        // undirected edge to the triangles on it and whether they run from the lower index
+        let mut edges = HashMap::<[u32; 2], Vec<(usize, bool)>>::new();
+        let mut edge_order = vec![];
+        for (triangle, tri) in indices.chunks_exact(3).enumerate() {
+            if let Some(index) = tri.iter().find(|i| **i as usize >= vertices.len()) {
+                issues.push(MeshIssue::IndexOutOfRange {
+                    triangle,
+                    index: *index,
+                });
+                continue;
+            }
+            let ids = [tri[0], tri[1], tri[2]];
+            let [a, b, c] = ids.map(|i| weld[i as usize]);
+            if a == b || b == c || c == a {
+                issues.push(MeshIssue::DuplicateIndex {
+                    triangle,
+                    vertices: ids,
+                });
+                continue;
+            }
+
+            let [pa, pb, pc] = ids.map(|i| vertices[i as usize]);
+            let longest = (pb - pa)
+                .length_squared()
+                .max((pc - pb).length_squared())
+                .max((pa - pc).length_squared());
+            if (pb - pa).cross(pc - pa).length() <= longest * f32::EPSILON {
+                issues.push(MeshIssue::DegenerateTriangle {
+                    triangle,
+                    vertices: ids,
+                });
+            }
+
+            for (from, to) in [(a, b), (b, c), (c, a)] {
+                let key = [from.min(to), from.max(to)];
+                let on_edge = edges.entry(key).or_insert_with(|| {
+                    edge_order.push(key);
+                    vec![]
+                });
+                on_edge.push((triangle, from < to));
+            }
+        }
+
+        let mut open_edges = vec![];
+        for edge in edge_order.iter() {
+            let triangles = &edges[edge];
+            match triangles.as_slice() {
+                [(triangle, forward)] => {
+                    let directed = if *forward { *edge } else { [edge[1], edge[0]] };
+                    open_edges.push((*triangle, directed));
+                }
+                [(a, a_forward), (b, b_forward)] => {
+                    if a_forward == b_forward {
+                        issues.push(MeshIssue::InconsistentWinding {
+                            edge: *edge,
+                            triangles: [*a, *b],
+                        });
+                    }
+                }
+                _ => issues.push(MeshIssue::NonManifoldEdge {
+                    edge: *edge,
+                    triangles: triangles.iter().map(|(t, _)| *t).collect(),
+                }),
+            }
+        }
+
+        let mut open_vertices: Vec<u32> = open_edges.iter().flat_map(|(_, e)| *e).collect();
+        open_vertices.sort();
+        open_vertices.dedup();
+        for (triangle, edge) in open_edges.iter() {
+            let [a, b] = edge.map(|i| vertices[i as usize]);
+            let length = (b - a).length();
+            for vertex in open_vertices.iter().filter(|v| !edge.contains(*v)) {
+                let p = vertices[*vertex as usize];
+                let t = (p - a).dot(b - a) / (length * length);
+                let off_line = (b - a).cross(p - a).length() / length;
+                if t > 0. && t < 1. && off_line <= length * 1e-5 {
+                    issues.push(MeshIssue::TJunction {
+                        triangle: *triangle,
+                        edge: *edge,
+                        vertex: *vertex,
+                    });
+                }
+            }
+        }
+
+        issues.extend(boundary_loops(&open_edges));
+
+        Self {
+            vertex_count: vertices.len(),
+            triangle_count: indices.len() / 3,
+            issues,
+        }
+    }
+
+    /// `None` unless the mesh is a triangle list with positions
+    pub fn from_mesh(mesh: &Mesh) -> Option<Self> {
+        if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+            return None;
+        }
+        let vertices: Vec<Vec3> = mesh
+            .attribute(Mesh::ATTRIBUTE_POSITION)?
+            .as_float3()?
+            .iter()
+            .map(|p| Vec3::from_array(*p))
+            .collect();
+        let indices: Vec<u32> = match mesh.indices() {
+            Some(Indices::U16(indices)) => indices.iter().map(|i| *i as u32).collect(),
+            Some(Indices::U32(indices)) => indices.clone(),
+            None => (0..vertices.len() as u32).collect(),
+        };
+        Some(Self::new(&vertices, &indices))
+    }
+
+    /// No issues besides open boundaries, which any mesh that isn't a closed surface has
+    pub fn is_valid(&self) -> bool {
+        self.issues.iter().all(MeshIssue::is_boundary)
+    }
+
+    /// Valid and without open boundaries
+    pub fn is_closed(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn boundaries(&self) -> impl Iterator<Item = &MeshIssue> {
+        self.issues.iter().filter(|issue| issue.is_boundary())
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &MeshIssue> {
+        self.issues.iter().filter(|issue| !issue.is_boundary())
+    }
+}
+
+impl TerrainMeshData {
+    pub fn validate(&self) -> MeshReport {
+        MeshReport::new(&self.vertices, &self.indices)
+    }
+}
+
+/// Chains the directed open edges into loops, starting from the first unused edge each time
+fn boundary_loops(open_edges: &[(usize, [u32; 2])]) -> Vec<MeshIssue> {
+    let mut outgoing = HashMap::<u32, Vec<u32>>::new();
+    for (_, [from, to]) in open_edges.iter().rev() {
+        outgoing.entry(*from).or_default().push(*to);
+    }
+
+    let mut loops = vec![];
+    for (_, [start, _]) in open_edges.iter() {
+        let mut vertices = vec![];
+        let mut current = *start;
+        let closed = loop {
+            let Some(next) = outgoing.get_mut(&current).and_then(|to| to.pop()) else {
+                break false;
+            };
+            vertices.push(current);
+            if next == *start {
+                break true;
+            }
+            current = next;
+        };
+        if vertices.is_empty() {
+            continue;
+        }
+        if !closed {
+            vertices.push(current);
+        }
+        loops.push(MeshIssue::OpenBoundary { vertices, closed });
+    }
+    loops
+}
+
+/// Outlines the issues of every entity with a `MeshReport`, open boundaries in yellow and
+/// everything else in red
+pub fn draw_mesh_issues(
+    mut gizmos: Gizmos,
+    meshes: Res<Assets<Mesh>>,
+    report_q: Query<(&MeshReport, &Handle<Mesh>, &GlobalTransform)>,
+) {
+    for (report, handle, transform) in report_q.iter() {
+        let Some(positions) = meshes
+            .get(handle)
+            .and_then(|mesh| mesh.attribute(Mesh::ATTRIBUTE_POSITION))
+            .and_then(|positions| positions.as_float3())
+        else {
+            continue;
+        };
+        let vertices: Vec<Vec3> = positions.iter().map(|p| Vec3::from_array(*p)).collect();
+        if vertices.len() != report.vertex_count {
+            // the mesh changed since it was validated
+            continue;
+        }
+
+        for issue in report.issues.iter() {
+            let color = if issue.is_boundary() { YELLOW } else { RED };
+            for [a, b] in issue.lines(&vertices) {
+                gizmos.line(
+                    transform.transform_point(a),
+                    transform.transform_point(b),
+                    color,
+                );
+            }
+        }
+    }
+}
+
+mod tests {
+    #![allow(unused)]
+    use super::{MeshIssue, MeshReport};
+    use crate::world::{
+        chunks::MarchingTileBundle,
+        noise::NoiseSampler,
+        rtin::{build_chunk_terrain_from_sampler, build_terrain_from_sampler},
+        wfc::{grid::TileCell, tile::TileID},
+    };
+    use bevy::{math::Vec3, prelude::*};
+    use noise::{Fbm, Perlin};
+
+    /// Two triangles making a unit square on the xz plane, facing up
+    fn quad() -> (Vec<Vec3>, Vec<u32>) {
+        let vertices = vec![
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0., 0., 1.),
+            Vec3::new(1., 0., 1.),
+            Vec3::new(1., 0., 0.),
+        ];
+        (vertices, vec![0, 1, 2, 0, 2, 3])
+    }
+
+    #[test]
+    fn quad_has_one_boundary() {
+        let (vertices, indices) = quad();
+        let report = MeshReport::new(&vertices, &indices);
+        assert!(report.is_valid());
+        assert_eq!(
+            report.issues,
+            vec![MeshIssue::OpenBoundary {
+                vertices: vec![0, 1, 2, 3],
+                closed: true
+            }]
+        );
+    }
+
+    #[test]
+    fn broken_triangles() {
+        let (mut vertices, mut indices) = quad();
+        vertices.push(Vec3::new(2., 0., 2.));
+        indices.extend([0, 0, 1, 0, 2, 9, 0, 2, 4]);
+        let report = MeshReport::new(&vertices, &indices);
+        let errors: Vec<_> = report.errors().collect();
+        assert_eq!(
+            errors,
+            vec![
+                &MeshIssue::DuplicateIndex {
+                    triangle: 2,
+                    vertices: [0, 0, 1]
+                },
+                &MeshIssue::IndexOutOfRange {
+                    triangle: 3,
+                    index: 9
+                },
+                &MeshIssue::DegenerateTriangle {
+                    triangle: 4,
+                    vertices: [0, 2, 4]
+                },
+                &MeshIssue::NonManifoldEdge {
+                    edge: [0, 2],
+                    triangles: vec![0, 1, 4]
+                },
+                // a flat triangle's long side runs past its middle corner
+                &MeshIssue::TJunction {
+                    triangle: 4,
+                    edge: [4, 0],
+                    vertex: 2
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn flipped_triangle() {
+        let (vertices, _) = quad();
+        let report = MeshReport::new(&vertices, &[0, 1, 2, 0, 3, 2]);
+        assert_eq!(
+            report.errors().collect::<Vec<_>>(),
+            vec![&MeshIssue::InconsistentWinding {
+                edge: [0, 2],
+                triangles: [0, 1]
+            }]
+        );
+    }
+
+    #[test]
+    fn t_junction() {
+        // the right triangle's long edge runs past the vertex splitting the left side in two
+        let vertices = vec![
+            Vec3::new(0., 0., 0.),
+            Vec3::new(0., 0., 2.),
+            Vec3::new(0., 0., 1.),
+            Vec3::new(-1., 0., 1.),
+            Vec3::new(1., 0., 1.),
+        ];
+        let indices = [0, 4, 1, 0, 2, 3, 2, 1, 3];
+        let report = MeshReport::new(&vertices, &indices);
+        assert_eq!(
+            report.errors().collect::<Vec<_>>(),
+            vec![&MeshIssue::TJunction {
+                triangle: 0,
+                edge: [1, 0],
+                vertex: 2
+            }]
+        );
+        // the outline, and the crack along the long edge
+        assert_eq!(report.boundaries().count(), 2);
+    }
+
+    #[test]
+    fn generated_terrain_is_valid() {
+        let sampler = NoiseSampler::single_layer(Fbm::<Perlin>::new(69));
+        for terrain in [
+            build_terrain_from_sampler(&sampler, 10., 32., 0.05),
+            build_chunk_terrain_from_sampler(&sampler, 10., 32., 0.05),
+        ] {
+            let report = terrain.validate();
+            assert!(report.is_valid(), "{:?}", report.issues);
+            assert_eq!(report.boundaries().count(), 1);
+            let mesh = terrain.into_mesh(false, 32.);
+            assert_eq!(MeshReport::from_mesh(&mesh), Some(report));
+        }
+    }
+
+    #[test]
+    fn wall_tile_is_closed() {
+        let cell = TileCell {
+            id: (TileID::WALL + TileID::ROT_0).into(),
+            x: 1,
+            z: 1,
+        };
+        let mesh = MarchingTileBundle::cell_mesh(&cell).unwrap();
+        let report = MeshReport::from_mesh(&mesh).unwrap();
+        assert_eq!(report.triangle_count, 12);
+        assert!(report.is_closed(), "{:?}", report.issues);
+    }
+}
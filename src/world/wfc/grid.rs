@@ -3,7 +3,7 @@ use super::{
     tile::{Orientation, TileID, TILE_CONNECTION_MAP},
 };
 use bevy::{prelude::*, utils::HashSet};
-use rand::{thread_rng, Rng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use std::{fmt::Debug, sync::LazyLock};
 
 #[derive(Debug, PartialEq, Clone)]
@@ -123,24 +123,21 @@ impl WaveGridCell {
         }
     }
 
-    fn force_collapse(&mut self) {
+    fn force_collapse(&mut self, rng: &mut impl Rng) {
         if let Self::Wave { ref wave, .. } = self {
-            let mut rng = thread_rng();
-            let mut idx = rng.gen_range(0..wave.possible.len());
-            if wave.possible.len() > 1 {
-                if let Some(empty_idx) = wave
-                    .possible
-                    .iter()
-                    .position(|id| id == &TileID::EMPTY.into())
+            let possible = wave.sorted_possible();
+            let mut idx = rng.gen_range(0..possible.len());
+            if possible.len() > 1 {
+                if let Some(empty_idx) = possible.iter().position(|id| id == &TileID::EMPTY.into())
                 {
                     while idx == empty_idx {
-                        idx = rng.gen_range(0..wave.possible.len());
+                        idx = rng.gen_range(0..possible.len());
                     }
                 }
             }
 
-            let tile = wave.possible.iter().nth(idx).unwrap();
-            self.collapse_into(*tile);
+            let tile = possible[idx];
+            self.collapse_into(tile);
         }
     }
 
@@ -165,6 +162,13 @@ impl Wave {
         }
         Self { possible }
     }
+
+    /// The set's iteration order changes between runs, a seeded collapse needs a stable one
+    fn sorted_possible(&self) -> Vec<TileID> {
+        let mut possible: Vec<TileID> = self.possible.iter().copied().collect();
+        possible.sort();
+        possible
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Eq)]
@@ -178,6 +182,7 @@ pub struct TileCell {
 pub struct WaveGrid {
     dimension_size: usize,
     heap_map: MinHeapMap<WaveGridCell>,
+    rng: StdRng,
 }
 
 impl TileCell {
@@ -232,7 +237,8 @@ impl TileCell {
 }
 
 impl WaveGrid {
-    pub fn new(size: u32) -> Self {
+    /// Collapses the same way every time for the same `seed`, see `SeedStream::WaveCollapse`
+    pub fn new(size: u32, seed: u64) -> Self {
         let mut heap_map = MinHeapMap::new();
         for z in 1..=size {
             for x in 1..=size {
@@ -243,6 +249,7 @@ impl WaveGrid {
         Self {
             heap_map,
             dimension_size: size as usize,
+            rng: StdRng::seed_from_u64(seed),
         }
     }
 
@@ -257,7 +264,7 @@ impl WaveGrid {
             match current {
                 WaveGridCell::Collapsed { .. } => break,
                 WaveGridCell::Wave { x, y, .. } => {
-                    current.force_collapse();
+                    current.force_collapse(&mut self.rng);
                     // println!("collapsed: {current:?}");
                     let neighbor_coords = self.neighbor_coords(x, y);
                     for (orient, coords) in neighbor_coords {
@@ -336,7 +343,7 @@ impl Wave {
 
         warn!("iterating through possibilites of self");
 
-        for id in &self.possible {
+        for id in &self.sorted_possible() {
             let possible_self_connects = connection_map.get(id).expect("this should not fail");
             if let Some(connect) = possible_self_connects.get(&collapsed_neighbor_orient) {
                 warn!(
@@ -365,3 +372,16 @@ impl Wave {
         }
     }
 }
+
+mod tests {
+    #![allow(unused)]
+    use super::WaveGrid;
+
+    #[test]
+    fn same_seed_same_grid() {
+        let a = WaveGrid::new(4, 9).collapse_all_into_vec();
+        let b = WaveGrid::new(4, 9).collapse_all_into_vec();
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 16);
+    }
+}
@@ -5,7 +5,7 @@ use bevy::{
 };
 use std::{fmt::Debug, ops::Add, sync::LazyLock};
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TileID(u8);
 
 impl From<u8> for TileID {